@@ -0,0 +1,126 @@
+#ifndef SMT_MAP_H
+#define SMT_MAP_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// An `SmtMap256` with the default storage.
+typedef struct SmtMap256 SmtMap256;
+
+// Success.
+#define SMT_OK 0
+
+// A required pointer argument is null.
+#define SMT_ERR_NULL_POINTER -1
+
+// An output buffer is too short.
+#define SMT_ERR_BUFFER_TOO_SMALL -2
+
+// A proof is not a valid encoding.
+#define SMT_ERR_MALFORMED_PROOF -3
+
+// A panic was caught.
+#define SMT_ERR_PANIC -4
+
+// Maximum length of an encoded merkle proof.
+#define SMT_MAX_PROOF_LEN (32 * 257)
+
+// Returns a new map where all keys have the default value (zero), or null on a panic. Release
+// it with `smt_map_free`.
+SmtMap256 *smt_map_new(void);
+
+// Releases a map returned by `smt_map_new`. Does nothing if `map` is null.
+//
+// # Safety
+//
+// `map` must be null or a handle returned by `smt_map_new` which has not been released yet.
+void smt_map_free(SmtMap256 *map);
+
+// Sets the value of a key. Writes the old value to `old_value_out` unless it is null.
+//
+// # Safety
+//
+// `map` must be a live handle, `key` and `value` must point to 32 readable bytes, and
+// `old_value_out` must be null or point to 32 writable bytes.
+int32_t smt_map_set(SmtMap256 *map,
+                    const uint8_t *key,
+                    const uint8_t *value,
+                    uint8_t *old_value_out);
+
+// Writes the value of a key to `value_out`.
+//
+// # Safety
+//
+// `map` must be a live handle, `key` must point to 32 readable bytes, and `value_out` must
+// point to 32 writable bytes.
+int32_t smt_map_get(const SmtMap256 *map, const uint8_t *key, uint8_t *value_out);
+
+// Writes the merkle root of the map to `root_out`.
+//
+// # Safety
+//
+// `map` must be a live handle, and `root_out` must point to 32 writable bytes.
+int32_t smt_map_root(const SmtMap256 *map, uint8_t *root_out);
+
+// Writes the value of a key to `value_out`, and its encoded merkle proof to `proof_out`. The
+// length of the proof is written to `proof_len_out` even if `proof_cap` is too small.
+//
+// # Safety
+//
+// `map` must be a live handle, `key` must point to 32 readable bytes, `value_out` must point to
+// 32 writable bytes, `proof_out` must point to `proof_cap` writable bytes, and `proof_len_out`
+// must be writable.
+int32_t smt_map_get_with_proof(const SmtMap256 *map,
+                               const uint8_t *key,
+                               uint8_t *value_out,
+                               uint8_t *proof_out,
+                               size_t proof_cap,
+                               size_t *proof_len_out);
+
+// Checks the encoded merkle proof of a key-value pair against a merkle root. Returns 1 if the
+// proof is valid, 0 if it is not, or a negative error code.
+//
+// # Safety
+//
+// `root`, `key` and `value` must point to 32 readable bytes, and `proof` must point to
+// `proof_len` readable bytes.
+int32_t smt_verify_merkle_proof(const uint8_t *root,
+                                const uint8_t *key,
+                                const uint8_t *value,
+                                const uint8_t *proof,
+                                size_t proof_len);
+
+// Encodes a merkle proof given as a bitmap and `num_hashes` consecutive 32-byte hashes. The
+// length of the encoding is written to `proof_len_out` even if `proof_cap` is too small.
+//
+// # Safety
+//
+// `bitmap` must point to 32 readable bytes, `hashes` to `32 * num_hashes` readable bytes,
+// `proof_out` to `proof_cap` writable bytes, and `proof_len_out` must be writable.
+int32_t smt_merkle_proof_encode(const uint8_t *bitmap,
+                                const uint8_t *hashes,
+                                size_t num_hashes,
+                                uint8_t *proof_out,
+                                size_t proof_cap,
+                                size_t *proof_len_out);
+
+// Decodes a merkle proof into its bitmap and up to `hashes_cap` consecutive 32-byte hashes. The
+// number of hashes is written to `num_hashes_out` even if `hashes_cap` is too small.
+//
+// # Safety
+//
+// `proof` must point to `proof_len` readable bytes, `bitmap_out` to 32 writable bytes,
+// `hashes_out` to `32 * hashes_cap` writable bytes, and `num_hashes_out` must be writable.
+int32_t smt_merkle_proof_decode(const uint8_t *proof,
+                                size_t proof_len,
+                                uint8_t *bitmap_out,
+                                uint8_t *hashes_out,
+                                size_t hashes_cap,
+                                size_t *num_hashes_out);
+
+#endif  /* SMT_MAP_H */
@@ -0,0 +1,85 @@
+"""Cross-checks the Python bindings against the vectors of the Rust tests in src/tests.rs.
+
+Build the module into the current environment with `maturin develop`, then run `pytest python`.
+"""
+
+import pytest
+
+from smt_map import MerkleProof, SmtMap256, check_merkle_proof
+
+ZERO = bytes(32)
+MAX_KEY = b"\xff" * 32
+EMPTY_ROOT = bytes.fromhex("a7ff9e28ffd3def443d324547688c2c4eb98edf7da757d6bfa22bff55b9ce24a")
+HASH_0 = bytes.fromhex("d6f751104ddfead9549c96fabdbd4d2fc6876c8cd9a49ea4a821de938f71a011")
+HASH_255 = bytes.fromhex("5a7ef746ad33334b4fbd7406a1a4ffa5c5f959199448d5ae6ed39b4a9d6ebe5a")
+
+
+def word(hex_string):
+    """Left-pads a hex string to 32 bytes, like `r256` in the Rust tests."""
+    return bytes.fromhex(hex_string.rjust(64, "0"))
+
+
+def sample_map():
+    smt = SmtMap256()
+    smt.set(ZERO, word("AA"))
+    smt.set(MAX_KEY, word("1234"))
+    return smt
+
+
+def test_merkle_root():
+    smt = SmtMap256()
+    assert smt.merkle_root() == EMPTY_ROOT
+    assert smt.set(ZERO, word("AA")) == ZERO
+    assert smt.merkle_root() == word(
+        "c2850844249b78ca4b416d5d8430c48a89b76e808648d4630275feadab00d0cd"
+    )
+    smt.set(MAX_KEY, word("1234"))
+    assert smt.merkle_root() == word(
+        "514f973cd76a4e5430119524ae291a3227f1e81f69f5bf2c61a36d2a6c3e239e"
+    )
+    assert smt.get(MAX_KEY) == word("1234")
+    assert len(smt) == 2
+
+
+def test_merkle_proof():
+    smt = sample_map()
+    key = word("C0")
+    value, proof = smt.get_with_proof(key)
+    assert value == ZERO
+    assert proof.bitmap == word("0200000000000000000000000000000000000000000000000000000000000080")
+    assert proof.hashes == [HASH_0, HASH_255]
+    assert proof == MerkleProof(proof.bitmap, [HASH_0, HASH_255])
+    assert smt.check_merkle_proof(key, value, proof)
+    assert check_merkle_proof(smt.merkle_root(), key, value, proof)
+
+    assert not check_merkle_proof(smt.merkle_root(), key, word("01"), proof)
+    assert not check_merkle_proof(EMPTY_ROOT, key, value, proof)
+    assert not check_merkle_proof(smt.merkle_root(), key, value, MerkleProof(proof.bitmap, [HASH_0]))
+
+
+def test_merkle_proof_bytes():
+    smt = sample_map()
+    _, proof = smt.get_with_proof(word("C0"))
+    encoded = proof.to_bytes()
+    assert encoded == proof.bitmap + HASH_0 + HASH_255
+    assert MerkleProof.from_bytes(encoded) == proof
+    with pytest.raises(ValueError):
+        MerkleProof.from_bytes(encoded[:-32])
+    with pytest.raises(ValueError):
+        MerkleProof.from_bytes(encoded + b"\x00")
+
+
+def test_invalid_arguments():
+    smt = SmtMap256()
+    with pytest.raises(ValueError):
+        smt.get(b"\x00" * 31)
+    with pytest.raises(ValueError):
+        smt.set(ZERO, b"\x00" * 33)
+    with pytest.raises(ValueError):
+        MerkleProof(ZERO, [b"\x00"])
+    with pytest.raises(TypeError):
+        smt.get("00" * 32)
+    with pytest.raises(TypeError):
+        MerkleProof(ZERO, [None])
+    with pytest.raises(ValueError):
+        check_merkle_proof(b"", ZERO, ZERO, MerkleProof(ZERO, []))
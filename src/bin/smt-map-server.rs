@@ -0,0 +1,50 @@
+//! HTTP/JSON server answering proof queries about a map persisted by `PersistentSmtMap`. See the
+//! `server` module for the endpoints.
+//!
+//! The bearer token of the update endpoint is read from the `SMT_MAP_SERVER_TOKEN` environment
+//! variable. The server prints `listening on <address>` once it accepts connections.
+
+use std::net::TcpListener;
+use std::process;
+use std::sync::Arc;
+
+use smt_map::persist::PersistentSmtMap;
+use smt_map::server::ProofServer;
+
+const USAGE: &str = "Usage: smt-map-server <map-dir> [--listen <address>]";
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8545";
+
+const TOKEN_VAR: &str = "SMT_MAP_SERVER_TOKEN";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (dir, address) = match args.as_slice() {
+        [dir] => (*dir, DEFAULT_ADDRESS),
+        [dir, "--listen", address] => (*dir, *address),
+        _ => fail(USAGE),
+    };
+    let token = match std::env::var(TOKEN_VAR) {
+        Ok(token) if !token.is_empty() => token,
+        _ => fail(&format!("error: {} must be set", TOKEN_VAR)),
+    };
+
+    let map = PersistentSmtMap::open(dir)
+        .unwrap_or_else(|e| fail(&format!("error: cannot open {}: {}", dir, e)));
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| fail(&format!("error: cannot listen on {}: {}", address, e)));
+    match listener.local_addr() {
+        Ok(address) => println!("listening on {}", address),
+        Err(e) => fail(&format!("error: {}", e)),
+    }
+
+    if let Err(e) = Arc::new(ProofServer::new(map, token)).serve(listener) {
+        fail(&format!("error: {}", e));
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
@@ -0,0 +1,208 @@
+//! Command-line tool for inspecting and patching maps persisted by `PersistentSmtMap`.
+//!
+//! Keys, values, roots and proofs are hex strings. Keys and values may be shorter than 64 hex
+//! digits, in which case they are left-padded with zeros. Proofs are `MerkleProof::to_bytes`.
+
+extern crate alloc;
+
+// The hex helpers of the library, which keeps them out of its public API.
+#[path = "../text.rs"]
+mod text;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process;
+
+use smt_map::persist::PersistentSmtMap;
+use smt_map::{check_merkle_proof, Hash256, MerkleProof};
+use text::to_hex;
+
+const USAGE: &str = "\
+Usage:
+    smt-map get <map-dir> <key>
+    smt-map set <map-dir> <key> <value>
+    smt-map root <map-dir>
+    smt-map prove <map-dir> <key>
+    smt-map verify <root> <key> <value> <proof>
+    smt-map import <map-dir> <dump-file>
+    smt-map export <map-dir> [<dump-file>]
+    smt-map stats <map-dir>
+
+A dump file has one `<key> <value>` pair per line. Empty lines and lines starting with `#` are
+ignored. `import` sets all the pairs of a dump in one atomic update.";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(Error::Usage) => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+        Err(Error::Invalid(message)) => {
+            eprintln!("error: {}", message);
+            process::exit(2);
+        }
+        Err(Error::Io(e)) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+enum Error {
+    Usage,
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+// Runs a command. Returns `Ok(false)` if a proof fails verification.
+fn run(args: &[String]) -> Result<bool, Error> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["get", dir, key] => {
+            let map = PersistentSmtMap::open(dir)?;
+            println!("{}", to_hex(map.get(&parse_word(key)?)));
+        }
+        ["set", dir, key, value] => {
+            let mut map = PersistentSmtMap::open(dir)?;
+            let old_value = map.set(&parse_word(key)?, parse_word(value)?)?;
+            println!("{}", to_hex(&old_value));
+        }
+        ["root", dir] => {
+            let map = PersistentSmtMap::open(dir)?;
+            println!("{}", to_hex(map.merkle_root()));
+        }
+        ["prove", dir, key] => {
+            let map = PersistentSmtMap::open(dir)?;
+            let (_, proof) = map.get_with_proof(&parse_word(key)?);
+            println!("{}", to_hex(&proof.to_bytes()));
+        }
+        ["verify", root, key, value, proof] => {
+            let proof = MerkleProof::from_bytes(&parse_hex(proof)?)
+                .ok_or_else(|| Error::Invalid("malformed proof".to_string()))?;
+            let valid = check_merkle_proof(
+                &parse_word(root)?,
+                &parse_word(key)?,
+                &parse_word(value)?,
+                &proof,
+            );
+            println!("{}", if valid { "valid" } else { "invalid" });
+            return Ok(valid);
+        }
+        ["import", dir, dump] => {
+            let mut updates = Vec::new();
+            for (i, line) in BufReader::new(File::open(dump)?).lines().enumerate() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let words: Vec<&str> = line.split_whitespace().collect();
+                let pair = match words.as_slice() {
+                    [key, value] => (parse_word(key)?, parse_word(value)?),
+                    _ => {
+                        return Err(Error::Invalid(format!(
+                            "line {}: expected `<key> <value>`",
+                            i + 1
+                        )))
+                    }
+                };
+                updates.push(pair);
+            }
+            let mut map = PersistentSmtMap::open(dir)?;
+            map.set_batch(&updates)?;
+            println!("{}", to_hex(map.merkle_root()));
+        }
+        ["export", dir] => export(&PersistentSmtMap::open(dir)?, io::stdout().lock())?,
+        ["export", dir, dump] => export(&PersistentSmtMap::open(dir)?, File::create(dump)?)?,
+        ["stats", dir] => {
+            let map = PersistentSmtMap::open(dir)?;
+            println!("entries: {}", map.map().len());
+            println!("nodes: {}", map.map().node_count());
+            println!("node log bytes: {}", map.node_log_len()?);
+            println!("root: {}", to_hex(map.merkle_root()));
+        }
+        _ => return Err(Error::Usage),
+    }
+    Ok(true)
+}
+
+fn export<W: Write>(map: &PersistentSmtMap, writer: W) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    for (key, value) in map.map().iter() {
+        writeln!(writer, "{} {}", to_hex(key), to_hex(value))?;
+    }
+    writer.flush()
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, Error> {
+    text::parse_hex(s).ok_or_else(|| Error::Invalid(format!("invalid hex string `{}`", s)))
+}
+
+fn parse_word(s: &str) -> Result<Hash256, Error> {
+    text::parse_word(s).ok_or_else(|| Error::Invalid(format!("`{}` is not a 32-byte hex word", s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        let dir = std::env::temp_dir().join(format!("smt_map_cli_{}", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir_str = dir.to_str().unwrap().to_string();
+        let run_args = |args: &[&str]| run(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+        assert!(run_args(&["set", &dir_str, "1", "aa"]).ok().unwrap());
+        let dump = dir.join("dump.txt");
+        std::fs::write(&dump, "# comment\n\n2 bb\n0x3 cc\n").unwrap();
+        assert!(run_args(&["import", &dir_str, dump.to_str().unwrap()])
+            .ok()
+            .unwrap());
+
+        let map = PersistentSmtMap::open(&dir).unwrap();
+        assert_eq!(map.map().len(), 3);
+        assert_eq!(
+            *map.get(&parse_word("3").ok().unwrap()),
+            parse_word("cc").ok().unwrap()
+        );
+        let root = to_hex(map.merkle_root());
+        let proof = to_hex(
+            &map.get_with_proof(&parse_word("2").ok().unwrap())
+                .1
+                .to_bytes(),
+        );
+        drop(map);
+
+        assert!(run_args(&["verify", &root, "2", "bb", &proof])
+            .ok()
+            .unwrap());
+        assert!(!run_args(&["verify", &root, "2", "bc", &proof])
+            .ok()
+            .unwrap());
+        assert!(run_args(&["verify", &root, "2", "bb", "00"]).is_err());
+        assert!(run_args(&["bogus"]).is_err());
+        let bad_key = "0x0x".to_string() + &"1".repeat(62);
+        assert!(run_args(&["get", &dir_str, &bad_key]).is_err());
+        assert!(run_args(&["get", &dir_str, "+1"]).is_err());
+
+        let exported = dir.join("export.txt");
+        assert!(run_args(&["export", &dir_str, exported.to_str().unwrap()])
+            .ok()
+            .unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&exported).unwrap().lines().count(),
+            3
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
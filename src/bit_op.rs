@@ -23,34 +23,34 @@ mod tests {
     fn test_bit_manipulation() {
         let mut u = [0_u8; 32];
         set_bit(&mut u, 0);
-        assert_eq!(hex(&u), "0100000000000000000000000000000000000000000000000000000000000000");
+        assert_eq!(hex(u), "0100000000000000000000000000000000000000000000000000000000000000");
         set_bit(&mut u, 255);
-        assert_eq!(hex(&u), "0100000000000000000000000000000000000000000000000000000000000080");
+        assert_eq!(hex(u), "0100000000000000000000000000000000000000000000000000000000000080");
         for i in 0..256 {
             set_bit(&mut u, i);
         }
-        assert_eq!(hex(&u), "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
+        assert_eq!(hex(u), "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
 
         clear_bit(&mut u, 0);
-        assert_eq!(hex(&u), "feffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
+        assert_eq!(hex(u), "feffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
         clear_bit(&mut u, 255);
-        assert_eq!(hex(&u), "feffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f");
+        assert_eq!(hex(u), "feffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f");
         clear_bit(&mut u, 126);
-        assert_eq!(hex(&u), "feffffffffffffffffffffffffffffbfffffffffffffffffffffffffffffff7f");
+        assert_eq!(hex(u), "feffffffffffffffffffffffffffffbfffffffffffffffffffffffffffffff7f");
         for i in 0..256 {
             clear_bit(&mut u, i);
         }
-        assert_eq!(hex(&u), "0000000000000000000000000000000000000000000000000000000000000000");
+        assert_eq!(hex(u), "0000000000000000000000000000000000000000000000000000000000000000");
 
         flip_bit(&mut u, 0);
-        assert_eq!(hex(&u), "0100000000000000000000000000000000000000000000000000000000000000");
+        assert_eq!(hex(u), "0100000000000000000000000000000000000000000000000000000000000000");
         flip_bit(&mut u, 255);
-        assert_eq!(hex(&u), "0100000000000000000000000000000000000000000000000000000000000080");
+        assert_eq!(hex(u), "0100000000000000000000000000000000000000000000000000000000000080");
         flip_bit(&mut u, 255);
-        assert_eq!(hex(&u), "0100000000000000000000000000000000000000000000000000000000000000");
+        assert_eq!(hex(u), "0100000000000000000000000000000000000000000000000000000000000000");
         for i in 0..256 {
             flip_bit(&mut u, i);
         }
-        assert_eq!(hex(&u), "feffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
+        assert_eq!(hex(u), "feffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff");
     }
 }
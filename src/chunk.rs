@@ -0,0 +1,426 @@
+//! Splitting an `SmtMap256` into independently verifiable chunks of key ranges.
+//!
+//! The chunks of a map partition the whole key space into ranges which are contiguous in tree
+//! order (the order of the leaves from left to right, see `NodeIndex`). Besides its entries,
+//! each chunk carries the merkle proofs of the first and the last key of its range. The proofs
+//! provide the hashes of all the subtrees left and right of the range, so the receiver can
+//! recompute the merkle root from a single chunk, which proves that the chunk holds exactly the
+//! non-default entries of the range.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+use crate::circuit::FullMerkleProof;
+use crate::hasher::Keccak256;
+use crate::order::{BitOrder, MsbFirst};
+use crate::{bit_op, merge_hashes, Hash256, Key, MerkleProof, NodeIndex, SmtMap256, Value};
+
+/// The entries of a map in a range of keys, with the proofs of the range boundaries.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Chunk {
+    /// The first key of the range, in tree order.
+    pub start: Key,
+
+    /// The last key of the range, in tree order.
+    pub end: Key,
+
+    /// The non-default entries in the range, sorted in tree order.
+    pub entries: Vec<(Key, Value)>,
+
+    /// Merkle proof of `start`.
+    pub start_proof: MerkleProof,
+
+    /// Merkle proof of `end`.
+    pub end_proof: MerkleProof,
+}
+
+/// Reason for rejecting a chunk.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChunkError {
+    /// The start of the range is after the end in tree order.
+    InvalidRange,
+
+    /// The entries are not strictly increasing in tree order, fall outside the range, or have
+    /// default values.
+    InvalidEntries,
+
+    /// The number of hashes in a boundary proof does not match its bitmap.
+    MalformedProof,
+
+    /// The merkle root recomputed from the chunk differs from the expected one.
+    RootMismatch,
+
+    /// The range overlaps with the range of a chunk imported before.
+    Overlap,
+
+    /// The imported chunks do not cover the whole key space yet.
+    Incomplete,
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ChunkError::InvalidRange => "invalid chunk range",
+            ChunkError::InvalidEntries => "invalid chunk entries",
+            ChunkError::MalformedProof => "malformed boundary proof",
+            ChunkError::RootMismatch => "merkle root mismatch",
+            ChunkError::Overlap => "overlapping chunk",
+            ChunkError::Incomplete => "incomplete chunks",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ChunkError {}
+
+impl SmtMap256 {
+    /// Splits the map into chunks of at most `max_entries` entries each. The chunks are returned
+    /// in tree order, and their ranges cover the whole key space.
+    pub fn export_chunks(&self, max_entries: usize) -> Vec<Chunk> {
+        assert!(max_entries > 0, "A chunk must be able to hold an entry");
+
+        let mut entries: Vec<(Key, Value)> = self
+            .kvs
+            .iter()
+            .filter(|(_, value)| **value != [0; 32])
+            .map(|(key, value)| (*key, *value))
+            .collect();
+        entries.sort_by(|a, b| tree_cmp(&a.0, &b.0));
+
+        let groups: Vec<&[(Key, Value)]> = if entries.is_empty() {
+            alloc::vec![&[]]
+        } else {
+            entries.chunks(max_entries).collect()
+        };
+        let mut chunks = Vec::with_capacity(groups.len());
+        for (i, group) in groups.iter().enumerate() {
+            let start = if i == 0 { [0; 32] } else { group[0].0 };
+            let end = match groups.get(i + 1) {
+                Some(next) => tree_predecessor(&next[0].0),
+                None => [0xff; 32],
+            };
+            chunks.push(Chunk {
+                start,
+                end,
+                entries: group.to_vec(),
+                start_proof: self.get_with_proof(&start).1,
+                end_proof: self.get_with_proof(&end).1,
+            });
+        }
+        chunks
+    }
+}
+
+impl Chunk {
+    /// Checks that the chunk holds exactly the non-default entries of its range in the map with
+    /// the given merkle root.
+    pub fn verify(&self, merkle_root: &Hash256) -> Result<(), ChunkError> {
+        if tree_cmp(&self.start, &self.end) == Ordering::Greater {
+            return Err(ChunkError::InvalidRange);
+        }
+        let mut prev: Option<&Key> = None;
+        for (key, value) in &self.entries {
+            let in_order = prev.is_none_or(|prev| tree_cmp(prev, key) == Ordering::Less);
+            if !in_order
+                || tree_cmp(key, &self.start) == Ordering::Less
+                || tree_cmp(key, &self.end) == Ordering::Greater
+                || *value == [0; 32]
+            {
+                return Err(ChunkError::InvalidEntries);
+            }
+            prev = Some(key);
+        }
+
+        let start_siblings = FullMerkleProof::from_proof::<Keccak256>(&self.start_proof)
+            .ok_or(ChunkError::MalformedProof)?
+            .siblings;
+        let end_siblings = FullMerkleProof::from_proof::<Keccak256>(&self.end_proof)
+            .ok_or(ChunkError::MalformedProof)?
+            .siblings;
+        let mut part = SmtMap256::new();
+        for (key, value) in &self.entries {
+            part.set(key, *value);
+        }
+        let range = RangeHasher {
+            start: tree_key(&self.start),
+            end: tree_key(&self.end),
+            part: &part,
+            start_siblings: &start_siblings,
+            end_siblings: &end_siblings,
+        };
+        if range.hash(&NodeIndex::root()) != *merkle_root {
+            return Err(ChunkError::RootMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Assembles a map from verified chunks, which may arrive in any order.
+pub struct ChunkImporter {
+    merkle_root: Hash256,
+
+    // The entries of the imported chunks, keyed by the start of their ranges in tree-key form
+    // (see `tree_key`), along with the end of the ranges.
+    ranges: BTreeMap<Key, (Key, Vec<(Key, Value)>)>,
+}
+
+impl ChunkImporter {
+    /// Returns an importer of the chunks of the map with the given merkle root.
+    pub fn new(merkle_root: &Hash256) -> Self {
+        Self {
+            merkle_root: *merkle_root,
+            ranges: BTreeMap::new(),
+        }
+    }
+
+    /// Verifies a chunk and keeps its entries.
+    pub fn import(&mut self, chunk: &Chunk) -> Result<(), ChunkError> {
+        chunk.verify(&self.merkle_root)?;
+
+        let (start, end) = (tree_key(&chunk.start), tree_key(&chunk.end));
+        let overlaps_prev = self
+            .ranges
+            .range(..=start)
+            .next_back()
+            .is_some_and(|(_, (prev_end, _))| *prev_end >= start);
+        let overlaps_next = self
+            .ranges
+            .range(start..)
+            .next()
+            .is_some_and(|(next_start, _)| *next_start <= end);
+        if overlaps_prev || overlaps_next {
+            return Err(ChunkError::Overlap);
+        }
+        self.ranges.insert(start, (end, chunk.entries.clone()));
+        Ok(())
+    }
+
+    /// Whether the imported chunks cover the whole key space.
+    pub fn is_complete(&self) -> bool {
+        let mut next_start = Some([0; 32]);
+        for (start, (end, _)) in &self.ranges {
+            if next_start != Some(*start) {
+                return false;
+            }
+            next_start = tree_key_successor(end);
+        }
+        next_start.is_none()
+    }
+
+    /// Returns the map assembled from the imported chunks, after confirming its merkle root.
+    pub fn finish(self) -> Result<SmtMap256, ChunkError> {
+        if !self.is_complete() {
+            return Err(ChunkError::Incomplete);
+        }
+        let mut map = SmtMap256::new();
+        for (_, entries) in self.ranges.values() {
+            for (key, value) in entries {
+                map.set(key, *value);
+            }
+        }
+        if *map.merkle_root() != self.merkle_root {
+            return Err(ChunkError::RootMismatch);
+        }
+        Ok(map)
+    }
+}
+
+// Recomputes the hash of a node from the entries of a range and the boundary proofs.
+struct RangeHasher<'a> {
+    // Range boundaries in tree-key form.
+    start: Key,
+    end: Key,
+
+    // A map holding only the entries of the range.
+    part: &'a SmtMap256,
+
+    start_siblings: &'a [Hash256],
+    end_siblings: &'a [Hash256],
+}
+
+impl<'a> RangeHasher<'a> {
+    // `index` must intersect the range.
+    fn hash(&self, index: &NodeIndex) -> Hash256 {
+        let (first, last) = key_range(index);
+        if self.start <= first && last <= self.end {
+            return *self.part.get_hash(index);
+        }
+
+        let (left, right) = (index.left_child(), index.right_child());
+        let left_hash = if key_range(&left).1 < self.start {
+            self.start_siblings[256 - left.depth]
+        } else {
+            self.hash(&left)
+        };
+        let right_hash = if key_range(&right).0 > self.end {
+            self.end_siblings[256 - right.depth]
+        } else {
+            self.hash(&right)
+        };
+        merge_hashes(&left_hash, &right_hash)
+    }
+}
+
+// Returns the first and the last key under a node, in tree-key form.
+fn key_range(index: &NodeIndex) -> (Key, Key) {
+    let mut last = index.bit_path;
+    for i in index.depth..256 {
+        bit_op::set_bit(&mut last, i);
+    }
+    (tree_key(&index.bit_path), tree_key(&last))
+}
+
+// Maps a key to a byte string whose lexicographic order is the tree order of the keys, that is the
+// key in `MsbFirst` order. The mapping is its own inverse.
+fn tree_key(key: &Key) -> Key {
+    MsbFirst::reorder(key)
+}
+
+// Compares two keys in tree order.
+fn tree_cmp(a: &Key, b: &Key) -> Ordering {
+    tree_key(a).cmp(&tree_key(b))
+}
+
+// Returns the key right before `key` in tree order. Panics if `key` is the first one.
+fn tree_predecessor(key: &Key) -> Key {
+    let mut result = tree_key(key);
+    for byte in result.iter_mut().rev() {
+        let (decremented, borrow) = byte.overflowing_sub(1);
+        *byte = decremented;
+        if !borrow {
+            return tree_key(&result);
+        }
+    }
+    panic!("The first key has no predecessor");
+}
+
+// Returns the tree-key right after `key`, or `None` if `key` is the last one.
+fn tree_key_successor(key: &Key) -> Option<Key> {
+    let mut result = *key;
+    for byte in result.iter_mut().rev() {
+        let (incremented, carry) = byte.overflowing_add(1);
+        *byte = incremented;
+        if !carry {
+            return Some(result);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_order_helpers() {
+        let mut key = [0; 32];
+        key[0] = 0x80;
+        assert_eq!(tree_predecessor(&key), {
+            let mut expected = [0xff; 32];
+            expected[0] = 0;
+            expected
+        });
+        key[0] = 0x01;
+        assert_eq!(tree_cmp(&key, &[0x80; 32]), Ordering::Greater);
+        assert_eq!(tree_key_successor(&[0xff; 32]), None);
+        let mut expected = [0; 32];
+        expected[31] = 1;
+        assert_eq!(tree_key_successor(&[0; 32]), Some(expected));
+    }
+
+    #[test]
+    fn test_export_and_import_chunks() {
+        let mut smt = SmtMap256::new();
+        for i in 1..=50_u8 {
+            let mut key = [i.wrapping_mul(113); 32];
+            key[31] = i;
+            smt.set(&key, [i; 32]);
+        }
+        smt.set(&[0; 32], [0xaa; 32]);
+        smt.set(&[0xff; 32], [0xbb; 32]);
+        for max_entries in &[1, 7, 52, 100] {
+            let chunks = smt.export_chunks(*max_entries);
+            assert_eq!(chunks.len(), 52_usize.div_ceil(*max_entries));
+            for chunk in &chunks {
+                assert!(chunk.entries.len() <= *max_entries);
+                assert_eq!(chunk.verify(smt.merkle_root()), Ok(()));
+            }
+
+            // Import in reverse order.
+            let mut importer = ChunkImporter::new(smt.merkle_root());
+            for chunk in chunks.iter().rev() {
+                assert!(!importer.is_complete());
+                importer.import(chunk).unwrap();
+            }
+            let map = importer.finish().unwrap();
+            assert_eq!(map.merkle_root(), smt.merkle_root());
+            assert_eq!(map.diff(&smt).count(), 0);
+        }
+
+        // An empty map has a single chunk without entries.
+        let empty = SmtMap256::new();
+        let chunks = empty.export_chunks(10);
+        assert_eq!(chunks.len(), 1);
+        let mut importer = ChunkImporter::new(empty.merkle_root());
+        importer.import(&chunks[0]).unwrap();
+        assert_eq!(importer.finish().unwrap().kvs.len(), 0);
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        let mut smt = SmtMap256::new();
+        for i in 1..=50_u8 {
+            let mut key = [i.wrapping_mul(113); 32];
+            key[31] = i;
+            smt.set(&key, [i; 32]);
+        }
+        smt.set(&[0; 32], [0xaa; 32]);
+        smt.set(&[0xff; 32], [0xbb; 32]);
+        let chunks = smt.export_chunks(10);
+        let root = smt.merkle_root();
+
+        // Missing, altered and extra entries.
+        let mut chunk = chunks[1].clone();
+        chunk.entries.remove(3);
+        assert_eq!(chunk.verify(root), Err(ChunkError::RootMismatch));
+        let mut chunk = chunks[1].clone();
+        chunk.entries[3].1[0] ^= 1;
+        assert_eq!(chunk.verify(root), Err(ChunkError::RootMismatch));
+        let mut chunk = chunks[1].clone();
+        chunk.entries.push((chunk.end, [1; 32]));
+        assert_eq!(chunk.verify(root), Err(ChunkError::RootMismatch));
+
+        // Entries out of order or out of range.
+        let mut chunk = chunks[1].clone();
+        chunk.entries.swap(0, 1);
+        assert_eq!(chunk.verify(root), Err(ChunkError::InvalidEntries));
+        let mut chunk = chunks[1].clone();
+        chunk.entries.push(chunks[2].entries[0]);
+        assert_eq!(chunk.verify(root), Err(ChunkError::InvalidEntries));
+
+        // A range extended over the entries of the next chunk.
+        let mut chunk = chunks[1].clone();
+        chunk.end = chunks[2].end;
+        chunk.end_proof = chunks[2].end_proof.clone();
+        assert_eq!(chunk.verify(root), Err(ChunkError::RootMismatch));
+
+        // Tampered proofs.
+        let mut chunk = chunks[1].clone();
+        chunk.start_proof.hashes.pop();
+        assert_eq!(chunk.verify(root), Err(ChunkError::MalformedProof));
+        let mut chunk = chunks[1].clone();
+        chunk.end_proof.hashes[0][0] ^= 1;
+        assert_eq!(chunk.verify(root), Err(ChunkError::RootMismatch));
+
+        // Overlapping and missing chunks.
+        let mut importer = ChunkImporter::new(root);
+        importer.import(&chunks[1]).unwrap();
+        assert_eq!(importer.import(&chunks[1]), Err(ChunkError::Overlap));
+        for chunk in &chunks[2..] {
+            importer.import(chunk).unwrap();
+        }
+        assert_eq!(importer.finish().err(), Some(ChunkError::Incomplete));
+    }
+}
@@ -0,0 +1,269 @@
+//! Fixed-shape merkle proofs and verification witnesses for ZK circuits.
+//!
+//! A `MerkleProof` only carries the non-default siblings, so its length depends on the map. A
+//! `FullMerkleProof` carries exactly one sibling per level, with the default hashes filled in, as
+//! circuits expect. Levels are numbered from the leaf up: level `i` merges a node of height `i`
+//! with its sibling into their parent of height `i + 1`.
+//!
+//! The siblings do not depend on the bit order of the map, but the keys and the proof bitmaps do.
+//! The functions without an order parameter are for maps with the default order (`LsbFirst`), and
+//! their `_with_order` variants for maps with the bit order `O`.
+
+use alloc::vec::Vec;
+
+use crate::hasher::Hasher;
+use crate::order::{BitOrder, LsbFirst};
+use crate::{bit_op, Hash256, Key, MerkleProof, Value};
+
+/// Merkle proof with the hashes of all the 256 siblings along the path.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FullMerkleProof {
+    /// The siblings, from the sibling of the leaf up to the sibling of the child of the root.
+    /// Always 256 hashes long.
+    pub siblings: Vec<Hash256>,
+}
+
+/// The hashes merged at a level while verifying a proof.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LevelWitness {
+    pub left: Hash256,
+    pub right: Hash256,
+    pub parent: Hash256,
+}
+
+impl FullMerkleProof {
+    /// Expands a proof of a map using the hasher `H`. Returns `None` if the number of hashes does
+    /// not match the bitmap.
+    pub fn from_proof<H: Hasher>(proof: &MerkleProof) -> Option<Self> {
+        Self::from_proof_with_order::<H, LsbFirst>(proof)
+    }
+
+    /// Same as `from_proof`, but for a map using the bit order `O`.
+    pub fn from_proof_with_order<H: Hasher, O: BitOrder>(proof: &MerkleProof) -> Option<Self> {
+        let bitmap = O::reorder(&proof.bitmap);
+        let mut iter = proof.hashes.iter();
+        let mut siblings = Vec::with_capacity(256);
+        for i in 0..256 {
+            if bit_op::get_bit(&bitmap, i) {
+                siblings.push(*iter.next()?);
+            } else {
+                siblings.push(H::default_hashes()[i]);
+            }
+        }
+        if iter.next().is_some() {
+            return None;
+        }
+        Some(Self { siblings })
+    }
+
+    /// Compresses the proof of a map using the hasher `H`, leaving out the default siblings. This
+    /// is the inverse of `from_proof` for the proofs returned by `SmtMap256::get_with_proof`.
+    /// Panics if the proof does not have 256 siblings.
+    pub fn to_proof<H: Hasher>(&self) -> MerkleProof {
+        self.to_proof_with_order::<H, LsbFirst>()
+    }
+
+    /// Same as `to_proof`, but for a map using the bit order `O`.
+    pub fn to_proof_with_order<H: Hasher, O: BitOrder>(&self) -> MerkleProof {
+        assert_eq!(self.siblings.len(), 256, "A full proof has 256 siblings");
+        let mut bitmap = [0; 32];
+        let mut hashes = Vec::new();
+        for (i, sibling) in self.siblings.iter().enumerate() {
+            if *sibling != H::default_hashes()[i] {
+                bit_op::set_bit(&mut bitmap, i);
+                hashes.push(*sibling);
+            }
+        }
+        MerkleProof {
+            bitmap: O::reorder(&bitmap),
+            hashes,
+        }
+    }
+
+    /// Returns the hashes merged at each level, from the leaf up, while computing the merkle root
+    /// from a key-value pair. The parent of the last level is the root. Returns `None` if the
+    /// proof does not have 256 siblings, or the hasher rejects some hash.
+    pub fn witness<H: Hasher>(&self, key: &Key, value: &Value) -> Option<Vec<LevelWitness>> {
+        self.witness_with_order::<H, LsbFirst>(key, value)
+    }
+
+    /// Same as `witness`, but for a map using the bit order `O`.
+    pub fn witness_with_order<H: Hasher, O: BitOrder>(
+        &self,
+        key: &Key,
+        value: &Value,
+    ) -> Option<Vec<LevelWitness>> {
+        if self.siblings.len() != 256 {
+            return None;
+        }
+        let mut hash = *value;
+        let mut levels = Vec::with_capacity(256);
+        for (sibling, is_right) in self
+            .siblings
+            .iter()
+            .zip(path_bits_with_order::<O>(key).iter())
+        {
+            let (left, right) = if *is_right {
+                (*sibling, hash)
+            } else {
+                (hash, *sibling)
+            };
+            hash = H::merge(&left, &right)?;
+            levels.push(LevelWitness {
+                left,
+                right,
+                parent: hash,
+            });
+        }
+        Some(levels)
+    }
+
+    /// Check the proof of a key-value pair in a map using the hasher `H` (specified by its merkle
+    /// root). Returns whether the proof is valid.
+    pub fn verify<H: Hasher>(&self, merkle_root: &Hash256, key: &Key, value: &Value) -> bool {
+        self.verify_with_order::<H, LsbFirst>(merkle_root, key, value)
+    }
+
+    /// Same as `verify`, but for a map using the bit order `O`.
+    pub fn verify_with_order<H: Hasher, O: BitOrder>(
+        &self,
+        merkle_root: &Hash256,
+        key: &Key,
+        value: &Value,
+    ) -> bool {
+        self.witness_with_order::<H, O>(key, value)
+            .is_some_and(|levels| levels[255].parent == *merkle_root)
+    }
+}
+
+/// Returns the bits of the path of a key in circuit order: bit `i` tells whether the node at level
+/// `i` (of height `i`) is a right child.
+pub fn path_bits(key: &Key) -> [bool; 256] {
+    path_bits_with_order::<LsbFirst>(key)
+}
+
+/// Same as `path_bits`, but for a map using the bit order `O`.
+pub fn path_bits_with_order<O: BitOrder>(key: &Key) -> [bool; 256] {
+    let key = O::reorder(key);
+    let mut bits = [false; 256];
+    for (i, bit) in bits.iter_mut().enumerate() {
+        *bit = bit_op::get_bit(&key, 255 - i);
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Keccak256;
+    use crate::order::MsbFirst;
+    use crate::store::BTreeStorage;
+    use crate::{check_merkle_proof, SmtMap256, DEFAULT_HASHES};
+
+    #[test]
+    fn test_full_merkle_proof() {
+        let mut smt = SmtMap256::new();
+        smt.set(&[0; 32], [0xaa; 32]);
+        smt.set(&[0xff; 32], [0xbb; 32]);
+        smt.set(&[0xc0; 32], [0xcc; 32]);
+        for key in [[0; 32], [0xc0; 32], [0xc1; 32], [0x0f; 32]] {
+            let (value, proof) = smt.get_with_proof(&key);
+            let full = FullMerkleProof::from_proof::<Keccak256>(&proof).unwrap();
+            assert_eq!(full.siblings.len(), 256);
+            assert_eq!(full.to_proof::<Keccak256>(), proof);
+            assert!(full.verify::<Keccak256>(smt.merkle_root(), &key, value));
+            assert!(!full.verify::<Keccak256>(smt.merkle_root(), &key, &[1; 32]));
+        }
+
+        let (_, proof) = SmtMap256::new().get_with_proof(&[0; 32]);
+        let full = FullMerkleProof::from_proof::<Keccak256>(&proof).unwrap();
+        assert_eq!(full.siblings[..], DEFAULT_HASHES[..256]);
+
+        let (_, mut proof) = smt.get_with_proof(&[0xc1; 32]);
+        proof.hashes.pop();
+        assert_eq!(FullMerkleProof::from_proof::<Keccak256>(&proof), None);
+        proof.hashes.extend_from_slice(&[[0; 32], [0; 32]]);
+        assert_eq!(FullMerkleProof::from_proof::<Keccak256>(&proof), None);
+    }
+
+    #[test]
+    fn test_full_merkle_proof_msb_first() {
+        let mut smt = SmtMap256::<BTreeStorage, Keccak256, MsbFirst>::default();
+        smt.set(&[0; 32], [0xaa; 32]);
+        smt.set(&[0xff; 32], [0xbb; 32]);
+        smt.set(&[0xc0; 32], [0xcc; 32]);
+        for key in [[0; 32], [0xc0; 32], [0xc1; 32], [0x0f; 32]] {
+            let (value, proof) = smt.get_with_proof(&key);
+            let full =
+                FullMerkleProof::from_proof_with_order::<Keccak256, MsbFirst>(&proof).unwrap();
+            assert_eq!(full.to_proof_with_order::<Keccak256, MsbFirst>(), proof);
+            assert!(full.verify_with_order::<Keccak256, MsbFirst>(smt.merkle_root(), &key, value));
+            if MsbFirst::reorder(&key) != key {
+                assert!(!full.verify::<Keccak256>(smt.merkle_root(), &key, value));
+            }
+        }
+
+        // The first bit of the key is the most significant bit of its first byte.
+        let mut key = [0; 32];
+        key[0] = 0b1000_0000;
+        key[31] = 0b0000_0001;
+        let bits = path_bits_with_order::<MsbFirst>(&key);
+        assert!(bits[0]);
+        assert!(bits[255]);
+        assert_eq!(bits.iter().filter(|bit| **bit).count(), 2);
+    }
+
+    #[test]
+    fn test_witness() {
+        let mut smt = SmtMap256::new();
+        smt.set(&[0; 32], [0xaa; 32]);
+        smt.set(&[0xff; 32], [0xbb; 32]);
+        smt.set(&[0xc0; 32], [0xcc; 32]);
+        let key = [0xc0; 32];
+        let (value, proof) = smt.get_with_proof(&key);
+        let full = FullMerkleProof::from_proof::<Keccak256>(&proof).unwrap();
+        let levels = full.witness::<Keccak256>(&key, value).unwrap();
+        let bits = path_bits(&key);
+
+        let mut hash = *value;
+        for (i, level) in levels.iter().enumerate() {
+            // The child on the path is on the side given by the path bit.
+            let (child, sibling) = if bits[i] {
+                (level.right, level.left)
+            } else {
+                (level.left, level.right)
+            };
+            assert_eq!(child, hash);
+            assert_eq!(sibling, full.siblings[i]);
+            assert_eq!(
+                Keccak256::merge(&level.left, &level.right),
+                Some(level.parent)
+            );
+            hash = level.parent;
+        }
+        assert_eq!(hash, *smt.merkle_root());
+        assert!(check_merkle_proof(&hash, &key, value, &proof));
+
+        let short = FullMerkleProof {
+            siblings: full.siblings[1..].to_vec(),
+        };
+        assert_eq!(short.witness::<Keccak256>(&key, value), None);
+        assert!(!short.verify::<Keccak256>(smt.merkle_root(), &key, value));
+    }
+
+    #[test]
+    fn test_path_bits() {
+        let mut key = [0; 32];
+        key[31] = 0b1010_0000;
+        key[0] = 0b0000_0001;
+        let bits = path_bits(&key);
+        // Bits are numbered from the least significant bit of each byte, and the last bits of the
+        // key decide the lowest levels.
+        assert!(bits[0]);
+        assert!(!bits[1]);
+        assert!(bits[2]);
+        // The first bit of the key is the bit of the child of the root.
+        assert!(bits[255]);
+        assert_eq!(bits.iter().filter(|bit| **bit).count(), 3);
+    }
+}
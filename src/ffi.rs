@@ -0,0 +1,290 @@
+//! C ABI for embedding `SmtMap256` in non-Rust programs. The header is `include/smt_map.h`,
+//! generated by cbindgen from this file alone (see `cbindgen.toml`).
+//!
+//! Maps are opaque handles created by `smt_map_new` and released by `smt_map_free`. Keys, values
+//! and hashes are 32-byte buffers. Merkle proofs are passed around in the encoding of
+//! `MerkleProof::to_bytes`: the 32-byte bitmap followed by the 32-byte hashes, which is at most
+//! `SMT_MAX_PROOF_LEN` bytes long. Functions writing variable-length output into a caller-provided
+//! buffer always report the needed length, and fail with `SMT_ERR_BUFFER_TOO_SMALL` if the
+//! buffer is shorter.
+//!
+//! All functions returning `int32_t` return `SMT_OK` (or 1 / 0 for verification results) on
+//! success, and a negative error code on failure. Panics do not unwind into the caller: they are
+//! reported as `SMT_ERR_PANIC`, after which the map passed in is in an unspecified state.
+
+use alloc::boxed::Box;
+use core::{ptr, slice};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{check_merkle_proof, Hash256, MerkleProof, SmtMap256};
+
+/// Success.
+pub const SMT_OK: i32 = 0;
+
+/// A required pointer argument is null.
+pub const SMT_ERR_NULL_POINTER: i32 = -1;
+
+/// An output buffer is too short.
+pub const SMT_ERR_BUFFER_TOO_SMALL: i32 = -2;
+
+/// A proof is not a valid encoding.
+pub const SMT_ERR_MALFORMED_PROOF: i32 = -3;
+
+/// A panic was caught.
+pub const SMT_ERR_PANIC: i32 = -4;
+
+/// Maximum length of an encoded merkle proof.
+pub const SMT_MAX_PROOF_LEN: usize = 32 * 257;
+
+/// Returns a new map where all keys have the default value (zero), or null on a panic. Release
+/// it with `smt_map_free`.
+#[no_mangle]
+pub extern "C" fn smt_map_new() -> *mut SmtMap256 {
+    panic::catch_unwind(|| Box::into_raw(Box::new(SmtMap256::new()))).unwrap_or(ptr::null_mut())
+}
+
+/// Releases a map returned by `smt_map_new`. Does nothing if `map` is null.
+///
+/// # Safety
+///
+/// `map` must be null or a handle returned by `smt_map_new` which has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn smt_map_free(map: *mut SmtMap256) {
+    if !map.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(map))));
+    }
+}
+
+/// Sets the value of a key. Writes the old value to `old_value_out` unless it is null.
+///
+/// # Safety
+///
+/// `map` must be a live handle, `key` and `value` must point to 32 readable bytes, and
+/// `old_value_out` must be null or point to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn smt_map_set(
+    map: *mut SmtMap256,
+    key: *const u8,
+    value: *const u8,
+    old_value_out: *mut u8,
+) -> i32 {
+    catch_panic(|| {
+        if map.is_null() || key.is_null() || value.is_null() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        let old_value = (*map).set(&read_hash(key), read_hash(value));
+        if !old_value_out.is_null() {
+            write_hash(old_value_out, &old_value);
+        }
+        SMT_OK
+    })
+}
+
+/// Writes the value of a key to `value_out`.
+///
+/// # Safety
+///
+/// `map` must be a live handle, `key` must point to 32 readable bytes, and `value_out` must
+/// point to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn smt_map_get(
+    map: *const SmtMap256,
+    key: *const u8,
+    value_out: *mut u8,
+) -> i32 {
+    catch_panic(|| {
+        if map.is_null() || key.is_null() || value_out.is_null() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        write_hash(value_out, (*map).get(&read_hash(key)));
+        SMT_OK
+    })
+}
+
+/// Writes the merkle root of the map to `root_out`.
+///
+/// # Safety
+///
+/// `map` must be a live handle, and `root_out` must point to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn smt_map_root(map: *const SmtMap256, root_out: *mut u8) -> i32 {
+    catch_panic(|| {
+        if map.is_null() || root_out.is_null() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        write_hash(root_out, (*map).merkle_root());
+        SMT_OK
+    })
+}
+
+/// Writes the value of a key to `value_out`, and its encoded merkle proof to `proof_out`. The
+/// length of the proof is written to `proof_len_out` even if `proof_cap` is too small.
+///
+/// # Safety
+///
+/// `map` must be a live handle, `key` must point to 32 readable bytes, `value_out` must point to
+/// 32 writable bytes, `proof_out` must point to `proof_cap` writable bytes, and `proof_len_out`
+/// must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn smt_map_get_with_proof(
+    map: *const SmtMap256,
+    key: *const u8,
+    value_out: *mut u8,
+    proof_out: *mut u8,
+    proof_cap: usize,
+    proof_len_out: *mut usize,
+) -> i32 {
+    catch_panic(|| {
+        if map.is_null() || key.is_null() || value_out.is_null() || proof_len_out.is_null() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        let (value, proof) = (*map).get_with_proof(&read_hash(key));
+        write_hash(value_out, value);
+        write_bytes(&proof.to_bytes(), proof_out, proof_cap, proof_len_out)
+    })
+}
+
+/// Checks the encoded merkle proof of a key-value pair against a merkle root. Returns 1 if the
+/// proof is valid, 0 if it is not, or a negative error code.
+///
+/// # Safety
+///
+/// `root`, `key` and `value` must point to 32 readable bytes, and `proof` must point to
+/// `proof_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn smt_verify_merkle_proof(
+    root: *const u8,
+    key: *const u8,
+    value: *const u8,
+    proof: *const u8,
+    proof_len: usize,
+) -> i32 {
+    catch_panic(|| {
+        if root.is_null() || key.is_null() || value.is_null() || proof.is_null() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        let proof = match MerkleProof::from_bytes(slice::from_raw_parts(proof, proof_len)) {
+            Some(proof) => proof,
+            None => return SMT_ERR_MALFORMED_PROOF,
+        };
+        check_merkle_proof(&read_hash(root), &read_hash(key), &read_hash(value), &proof) as i32
+    })
+}
+
+/// Encodes a merkle proof given as a bitmap and `num_hashes` consecutive 32-byte hashes. The
+/// length of the encoding is written to `proof_len_out` even if `proof_cap` is too small.
+///
+/// # Safety
+///
+/// `bitmap` must point to 32 readable bytes, `hashes` to `32 * num_hashes` readable bytes,
+/// `proof_out` to `proof_cap` writable bytes, and `proof_len_out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn smt_merkle_proof_encode(
+    bitmap: *const u8,
+    hashes: *const u8,
+    num_hashes: usize,
+    proof_out: *mut u8,
+    proof_cap: usize,
+    proof_len_out: *mut usize,
+) -> i32 {
+    catch_panic(|| {
+        if bitmap.is_null() || (hashes.is_null() && num_hashes > 0) || proof_len_out.is_null() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        let hashes = if num_hashes == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(hashes, 32 * num_hashes)
+        };
+        let proof = MerkleProof {
+            bitmap: read_hash(bitmap),
+            hashes: hashes
+                .chunks(32)
+                .map(|hash| read_hash(hash.as_ptr()))
+                .collect(),
+        };
+        let bytes = proof.to_bytes();
+        if MerkleProof::from_bytes(&bytes).is_none() {
+            // The number of hashes does not match the bitmap.
+            return SMT_ERR_MALFORMED_PROOF;
+        }
+        write_bytes(&bytes, proof_out, proof_cap, proof_len_out)
+    })
+}
+
+/// Decodes a merkle proof into its bitmap and up to `hashes_cap` consecutive 32-byte hashes. The
+/// number of hashes is written to `num_hashes_out` even if `hashes_cap` is too small.
+///
+/// # Safety
+///
+/// `proof` must point to `proof_len` readable bytes, `bitmap_out` to 32 writable bytes,
+/// `hashes_out` to `32 * hashes_cap` writable bytes, and `num_hashes_out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn smt_merkle_proof_decode(
+    proof: *const u8,
+    proof_len: usize,
+    bitmap_out: *mut u8,
+    hashes_out: *mut u8,
+    hashes_cap: usize,
+    num_hashes_out: *mut usize,
+) -> i32 {
+    catch_panic(|| {
+        if proof.is_null() || bitmap_out.is_null() || num_hashes_out.is_null() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        let proof = match MerkleProof::from_bytes(slice::from_raw_parts(proof, proof_len)) {
+            Some(proof) => proof,
+            None => return SMT_ERR_MALFORMED_PROOF,
+        };
+        *num_hashes_out = proof.hashes.len();
+        if proof.hashes.len() > hashes_cap {
+            return SMT_ERR_BUFFER_TOO_SMALL;
+        }
+        if hashes_out.is_null() && !proof.hashes.is_empty() {
+            return SMT_ERR_NULL_POINTER;
+        }
+        write_hash(bitmap_out, &proof.bitmap);
+        for (i, hash) in proof.hashes.iter().enumerate() {
+            write_hash(hashes_out.add(32 * i), hash);
+        }
+        SMT_OK
+    })
+}
+
+// Runs the body of a function returning an error code, turning a panic into `SMT_ERR_PANIC`.
+fn catch_panic<F: FnOnce() -> i32>(body: F) -> i32 {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(SMT_ERR_PANIC)
+}
+
+unsafe fn read_hash(ptr: *const u8) -> Hash256 {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(slice::from_raw_parts(ptr, 32));
+    hash
+}
+
+unsafe fn write_hash(ptr: *mut u8, hash: &Hash256) {
+    slice::from_raw_parts_mut(ptr, 32).copy_from_slice(hash);
+}
+
+unsafe fn write_bytes(bytes: &[u8], out: *mut u8, cap: usize, len_out: *mut usize) -> i32 {
+    *len_out = bytes.len();
+    if bytes.len() > cap {
+        return SMT_ERR_BUFFER_TOO_SMALL;
+    }
+    if out.is_null() {
+        return SMT_ERR_NULL_POINTER;
+    }
+    slice::from_raw_parts_mut(out, bytes.len()).copy_from_slice(bytes);
+    SMT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|| SMT_OK), SMT_OK);
+        assert_eq!(catch_panic(|| panic!("boom")), SMT_ERR_PANIC);
+    }
+}
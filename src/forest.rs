@@ -0,0 +1,359 @@
+//! Many `SmtMap256`-compatible maps sharing one deduplicated node store.
+//!
+//! The store is content-addressed: an inner node is stored once under its hash, with the hashes of
+//! its two children, however many maps or places in a map have it. Default subtrees and leaves
+//! (whose hashes are their values) are never stored. A map is just the hash of its root, so
+//! forking a map is free, and setting a value copies the path to the root only.
+//!
+//! Nodes are reference counted, by the parent nodes which have them as children and by the maps
+//! which have them as roots. Dropping a map releases its root, and the nodes which are no longer
+//! referenced are removed right away.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{
+    bit_op, check_merkle_proof, merge_hashes, Hash256, Key, MerkleProof, Value, DEFAULT_HASHES,
+};
+
+/// Handle of a map in a `Forest`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct MapId(u64);
+
+/// A map of a `Forest` has missing or corrupt nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IntegrityError(pub MapId);
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "missing or corrupt nodes in map {}", (self.0).0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for IntegrityError {}
+
+/// A stored inner node.
+#[derive(Clone, Debug)]
+struct ForestNode {
+    left: Hash256,
+    right: Hash256,
+
+    // The number of parent nodes and maps referencing this node.
+    refs: usize,
+}
+
+/// Store of the nodes of many maps from 256-bit keys to 256-bit values, with the same merkle roots
+/// and proofs as `SmtMap256`.
+#[derive(Clone, Default)]
+pub struct Forest {
+    nodes: BTreeMap<Hash256, ForestNode>,
+
+    // The roots of the maps.
+    roots: BTreeMap<MapId, Hash256>,
+
+    next_id: u64,
+}
+
+impl Forest {
+    /// Returns an empty forest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a map where all keys have the default value (zero), and returns its handle.
+    pub fn create_map(&mut self) -> MapId {
+        self.add_map(DEFAULT_HASHES[256])
+    }
+
+    /// Adds a copy of a map, and returns its handle. The copy shares all the nodes of the map.
+    /// Panics if the map is not in the forest.
+    pub fn fork(&mut self, id: MapId) -> MapId {
+        let root = *self.root(id);
+        self.retain(&root, 256);
+        self.add_map(root)
+    }
+
+    /// Removes a map, and the nodes no other map references. Panics if the map is not in the
+    /// forest.
+    pub fn drop_map(&mut self, id: MapId) {
+        let root = self.roots.remove(&id).expect("Unknown map");
+        self.release(&root, 256);
+    }
+
+    /// Sets the value of a key in a map. Returns the old value of the key. Panics if the map is
+    /// not in the forest.
+    pub fn set(&mut self, id: MapId, key: &Key, value: Value) -> Value {
+        let old_root = *self.root(id);
+        let (old_value, siblings) = self.walk(&old_root, key);
+
+        // Copy the path from the leaf up.
+        let mut hash = value;
+        for (i, sibling) in siblings.iter().enumerate() {
+            let (left, right) = if bit_op::get_bit(key, 255 - i) {
+                (sibling, &hash)
+            } else {
+                (&hash, sibling)
+            };
+            hash = self.insert_node(left, right, i + 1);
+        }
+
+        self.retain(&hash, 256);
+        self.roots.insert(id, hash);
+        self.release(&old_root, 256);
+        old_value
+    }
+
+    /// Returns the value of a key in a map. Panics if the map is not in the forest.
+    pub fn get(&self, id: MapId, key: &Key) -> Value {
+        self.walk(self.root(id), key).0
+    }
+
+    /// Returns the value of a key in a map with merkle proof, which is the same as the proof of
+    /// the key in an `SmtMap256` with the same values. Panics if the map is not in the forest.
+    pub fn get_with_proof(&self, id: MapId, key: &Key) -> (Value, MerkleProof) {
+        let (value, siblings) = self.walk(self.root(id), key);
+        let mut bitmap = [0; 32];
+        let mut hashes = Vec::new();
+        for (i, sibling) in siblings.iter().enumerate() {
+            if *sibling != DEFAULT_HASHES[i] {
+                bit_op::set_bit(&mut bitmap, i);
+                hashes.push(*sibling);
+            }
+        }
+        (value, MerkleProof { bitmap, hashes })
+    }
+
+    /// Returns the merkle root of a map. Panics if the map is not in the forest.
+    pub fn merkle_root(&self, id: MapId) -> &Hash256 {
+        self.root(id)
+    }
+
+    /// Returns the number of maps.
+    pub fn map_count(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Returns the number of stored nodes, shared by all the maps.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks every map with `check_map`. Returns the first map failing the check.
+    pub fn integrity_check(&self) -> Result<(), IntegrityError> {
+        match self.roots.keys().find(|id| !self.check_map(**id)) {
+            Some(id) => Err(IntegrityError(*id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that all the nodes of a map are stored and hash to their children, and that the
+    /// proofs of all the keys with non-default values are valid. Panics if the map is not in the
+    /// forest.
+    pub fn check_map(&self, id: MapId) -> bool {
+        let root = self.root(id);
+        let mut keys = Vec::new();
+        if !self.collect_keys(root, 256, [0; 32], &mut keys) {
+            return false;
+        }
+        keys.iter().all(|key| {
+            let (value, proof) = self.get_with_proof(id, key);
+            check_merkle_proof(root, key, &value, &proof)
+        })
+    }
+
+    fn add_map(&mut self, root: Hash256) -> MapId {
+        let id = MapId(self.next_id);
+        self.next_id += 1;
+        self.roots.insert(id, root);
+        id
+    }
+
+    fn root(&self, id: MapId) -> &Hash256 {
+        self.roots.get(&id).expect("Unknown map")
+    }
+
+    /// Returns the value of a key in the tree with the given root, and the hashes of the
+    /// siblings along the path, from the sibling of the leaf up.
+    fn walk(&self, root: &Hash256, key: &Key) -> (Value, Vec<Hash256>) {
+        let mut siblings = alloc::vec![[0; 32]; 256];
+        let mut hash = *root;
+        for depth in 0..256 {
+            let height = 256 - depth;
+            let (left, right) = if hash == DEFAULT_HASHES[height] {
+                (DEFAULT_HASHES[height - 1], DEFAULT_HASHES[height - 1])
+            } else {
+                let node = &self.nodes[&hash];
+                (node.left, node.right)
+            };
+            let (child, sibling) = if bit_op::get_bit(key, depth) {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            siblings[height - 1] = sibling;
+            hash = child;
+        }
+        (hash, siblings)
+    }
+
+    /// Appends to `keys` the keys with non-default values in the subtree of the given height, root
+    /// and path. Returns whether the nodes of the subtree are stored and hash to their children.
+    fn collect_keys(
+        &self,
+        hash: &Hash256,
+        height: usize,
+        mut path: Key,
+        keys: &mut Vec<Key>,
+    ) -> bool {
+        if *hash == DEFAULT_HASHES[height] {
+            return true;
+        }
+        if height == 0 {
+            keys.push(path);
+            return true;
+        }
+        let node = match self.nodes.get(hash) {
+            Some(node) if node.refs > 0 && merge_hashes(&node.left, &node.right) == *hash => node,
+            _ => return false,
+        };
+        if !self.collect_keys(&node.left, height - 1, path, keys) {
+            return false;
+        }
+        bit_op::set_bit(&mut path, 256 - height);
+        self.collect_keys(&node.right, height - 1, path, keys)
+    }
+
+    /// Stores the node of the given height with the given children, if not default, and returns
+    /// its hash. A new node references its children, but is itself unreferenced.
+    fn insert_node(&mut self, left: &Hash256, right: &Hash256, height: usize) -> Hash256 {
+        let hash = merge_hashes(left, right);
+        if hash == DEFAULT_HASHES[height] || self.nodes.contains_key(&hash) {
+            return hash;
+        }
+        self.retain(left, height - 1);
+        self.retain(right, height - 1);
+        let node = ForestNode {
+            left: *left,
+            right: *right,
+            refs: 0,
+        };
+        self.nodes.insert(hash, node);
+        hash
+    }
+
+    fn retain(&mut self, hash: &Hash256, height: usize) {
+        if height > 0 && *hash != DEFAULT_HASHES[height] {
+            self.nodes.get_mut(hash).unwrap().refs += 1;
+        }
+    }
+
+    fn release(&mut self, hash: &Hash256, height: usize) {
+        if height == 0 || *hash == DEFAULT_HASHES[height] {
+            return;
+        }
+        let node = self.nodes.get_mut(hash).unwrap();
+        node.refs -= 1;
+        if node.refs == 0 {
+            let node = self.nodes.remove(hash).unwrap();
+            self.release(&node.left, height - 1);
+            self.release(&node.right, height - 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SmtMap256;
+
+    #[test]
+    fn test_forest() {
+        let mut forest = Forest::new();
+        let mut smt = SmtMap256::new();
+        let id = forest.create_map();
+        assert_eq!(forest.merkle_root(id), smt.merkle_root());
+
+        for i in 0..32_u8 {
+            let key = [i.wrapping_mul(37); 32];
+            assert_eq!(forest.set(id, &key, [i + 1; 32]), *smt.get(&key));
+            smt.set(&key, [i + 1; 32]);
+        }
+        assert_eq!(forest.set(id, &[0; 32], [0xaa; 32]), [1; 32]);
+        smt.set(&[0; 32], [0xaa; 32]);
+        forest.set(id, &[37; 32], [0; 32]);
+        smt.set(&[37; 32], [0; 32]);
+        assert_eq!(forest.merkle_root(id), smt.merkle_root());
+
+        for key in [[0; 32], [37; 32], [74; 32], [0xff; 32]] {
+            assert_eq!(forest.get(id, &key), *smt.get(&key));
+            let (value, proof) = forest.get_with_proof(id, &key);
+            assert_eq!((&value, proof), smt.get_with_proof(&key));
+        }
+
+        // The store has the same inner nodes as the map.
+        let leaves = smt.len();
+        assert_eq!(forest.node_count(), smt.node_count() - leaves);
+
+        forest.drop_map(id);
+        assert_eq!(forest.map_count(), 0);
+        assert_eq!(forest.node_count(), 0);
+    }
+
+    #[test]
+    fn test_shared_nodes() {
+        let mut forest = Forest::new();
+        let tenants: Vec<MapId> = (0..8).map(|_| forest.create_map()).collect();
+        for id in &tenants {
+            forest.set(*id, &[1; 32], [0xaa; 32]);
+            forest.set(*id, &[2; 32], [0xbb; 32]);
+        }
+        // Identical maps share all their nodes: the root, and the nodes of the two paths below
+        // it, as the keys differ in their first bit.
+        assert_eq!(forest.node_count(), 1 + 2 * 255);
+
+        // A different value copies the path of its key only.
+        forest.set(tenants[0], &[2; 32], [0xcc; 32]);
+        assert_eq!(forest.node_count(), 1 + 2 * 255 + 256);
+        assert_eq!(forest.get(tenants[0], &[2; 32]), [0xcc; 32]);
+        assert_eq!(forest.get(tenants[1], &[2; 32]), [0xbb; 32]);
+
+        // Forks share nodes with the original, and survive it.
+        let fork = forest.fork(tenants[0]);
+        assert_eq!(forest.node_count(), 1 + 2 * 255 + 256);
+        forest.drop_map(tenants[0]);
+        assert_eq!(forest.node_count(), 1 + 2 * 255 + 256);
+        assert_eq!(forest.get(fork, &[2; 32]), [0xcc; 32]);
+        forest.drop_map(fork);
+        assert_eq!(forest.node_count(), 1 + 2 * 255);
+
+        for id in &tenants[1..] {
+            forest.drop_map(*id);
+        }
+        assert_eq!(forest.node_count(), 0);
+    }
+
+    #[test]
+    fn test_integrity_check() {
+        let mut forest = Forest::new();
+        let (id1, id2) = (forest.create_map(), forest.create_map());
+        forest.set(id1, &[1; 32], [0xaa; 32]);
+        forest.set(id2, &[1; 32], [0xaa; 32]);
+        forest.set(id2, &[0x80; 32], [0xbb; 32]);
+        assert_eq!(forest.integrity_check(), Ok(()));
+
+        // A node only in the second map is lost.
+        let mut corrupt = forest.clone();
+        let (_, proof) = forest.get_with_proof(id2, &[1; 32]);
+        corrupt.nodes.remove(&proof.hashes[0]);
+        assert!(corrupt.check_map(id1));
+        assert_eq!(corrupt.integrity_check(), Err(IntegrityError(id2)));
+
+        // A node no longer hashes to its children.
+        let mut corrupt = forest;
+        let root = *corrupt.merkle_root(id1);
+        corrupt.nodes.get_mut(&root).unwrap().left = [0xcc; 32];
+        assert_eq!(corrupt.integrity_check(), Err(IntegrityError(id1)));
+    }
+}
@@ -0,0 +1,252 @@
+//! Candidate states of a chain which may reorganize, sharing their nodes in a `Forest`.
+//!
+//! Every block has the state of a map. The finalized block is the root of a tree of blocks, and
+//! forking a block adds a child block starting from a copy of its state. The states of the blocks
+//! are independent afterwards: setting a value in a block changes neither its parent nor its
+//! children. Finalizing a block discards every block which does not descend from it, with the
+//! nodes only these blocks reached.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::forest::{Forest, MapId};
+use crate::{Hash256, Key, MerkleProof, Value};
+
+/// Error of the updates of a `ForkTree`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ForkError {
+    /// The block is not in the tree, or has been discarded.
+    UnknownBlock(Hash256),
+
+    /// The block is already in the tree.
+    DuplicateBlock(Hash256),
+}
+
+impl fmt::Display for ForkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (message, block) = match self {
+            ForkError::UnknownBlock(block) => ("unknown block", block),
+            ForkError::DuplicateBlock(block) => ("duplicate block", block),
+        };
+        write!(f, "{}: 0x", message)?;
+        for byte in block {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ForkError {}
+
+/// A block of a `ForkTree`.
+#[derive(Clone, Debug)]
+struct Block {
+    map: MapId,
+
+    // `None` for the finalized block.
+    parent: Option<Hash256>,
+}
+
+/// Tree of blocks keyed by block hash, each with the state of an `SmtMap256`-compatible map.
+#[derive(Clone)]
+pub struct ForkTree {
+    forest: Forest,
+
+    // The live blocks.
+    blocks: BTreeMap<Hash256, Block>,
+
+    finalized: Hash256,
+}
+
+impl ForkTree {
+    /// Returns a tree with the given finalized block, whose map has the default value (zero) for
+    /// all keys.
+    pub fn new(genesis: Hash256) -> Self {
+        let mut forest = Forest::new();
+        let map = forest.create_map();
+        let mut blocks = BTreeMap::new();
+        blocks.insert(genesis, Block { map, parent: None });
+        Self {
+            forest,
+            blocks,
+            finalized: genesis,
+        }
+    }
+
+    /// Adds `block` as a child of `parent`, with a copy of the state of `parent`.
+    pub fn fork(&mut self, parent: &Hash256, block: Hash256) -> Result<(), ForkError> {
+        let parent_map = self.block(parent)?.map;
+        if self.blocks.contains_key(&block) {
+            return Err(ForkError::DuplicateBlock(block));
+        }
+        let map = self.forest.fork(parent_map);
+        let parent = Some(*parent);
+        self.blocks.insert(block, Block { map, parent });
+        Ok(())
+    }
+
+    /// Sets the value of a key in the state of a block. Returns the old value of the key.
+    pub fn set(&mut self, block: &Hash256, key: &Key, value: Value) -> Result<Value, ForkError> {
+        let map = self.block(block)?.map;
+        Ok(self.forest.set(map, key, value))
+    }
+
+    /// Makes `block` the finalized block, and discards the blocks which do not descend from it.
+    pub fn finalize(&mut self, block: &Hash256) -> Result<(), ForkError> {
+        self.block(block)?;
+        let discarded: Vec<Hash256> = self
+            .blocks
+            .keys()
+            .filter(|other| !self.descends_from(other, block))
+            .copied()
+            .collect();
+        for other in discarded {
+            let map = self.blocks.remove(&other).unwrap().map;
+            self.forest.drop_map(map);
+        }
+        self.blocks.get_mut(block).unwrap().parent = None;
+        self.finalized = *block;
+        Ok(())
+    }
+
+    /// Returns the value of a key in the state of a block, or `None` if the block is not live.
+    pub fn get(&self, block: &Hash256, key: &Key) -> Option<Value> {
+        let map = self.blocks.get(block)?.map;
+        Some(self.forest.get(map, key))
+    }
+
+    /// Returns the value of a key in the state of a block with merkle proof, or `None` if the
+    /// block is not live.
+    pub fn get_with_proof(&self, block: &Hash256, key: &Key) -> Option<(Value, MerkleProof)> {
+        let map = self.blocks.get(block)?.map;
+        Some(self.forest.get_with_proof(map, key))
+    }
+
+    /// Returns the merkle root of the state of a block, or `None` if the block is not live.
+    pub fn merkle_root(&self, block: &Hash256) -> Option<&Hash256> {
+        let map = self.blocks.get(block)?.map;
+        Some(self.forest.merkle_root(map))
+    }
+
+    /// Returns the parent of a live block, or `None` for the finalized block and the blocks which
+    /// are not live.
+    pub fn parent(&self, block: &Hash256) -> Option<&Hash256> {
+        self.blocks.get(block)?.parent.as_ref()
+    }
+
+    /// Whether a block is live: the finalized block or one of its descendants.
+    pub fn contains(&self, block: &Hash256) -> bool {
+        self.blocks.contains_key(block)
+    }
+
+    /// Returns the finalized block.
+    pub fn finalized(&self) -> &Hash256 {
+        &self.finalized
+    }
+
+    /// Returns the number of live blocks, the finalized one included.
+    pub fn live_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns the number of stored nodes of the states of all the live blocks.
+    pub fn node_count(&self) -> usize {
+        self.forest.node_count()
+    }
+
+    fn block(&self, block: &Hash256) -> Result<&Block, ForkError> {
+        self.blocks
+            .get(block)
+            .ok_or(ForkError::UnknownBlock(*block))
+    }
+
+    /// Whether `block` is `ancestor` or one of its descendants.
+    fn descends_from(&self, block: &Hash256, ancestor: &Hash256) -> bool {
+        let mut current = Some(block);
+        while let Some(block) = current {
+            if block == ancestor {
+                return true;
+            }
+            current = self.blocks[block].parent.as_ref();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check_merkle_proof, SmtMap256};
+
+    #[test]
+    fn test_fork_tree() {
+        let (genesis, a, b, a1, a2, b1) = (
+            [0; 32], [0xa; 32], [0xb; 32], [0xa1; 32], [0xa2; 32], [0xb1; 32],
+        );
+        let mut tree = ForkTree::new(genesis);
+        tree.set(&genesis, &[1; 32], [0x11; 32]).unwrap();
+
+        // genesis -> a -> a1, a2 and genesis -> b -> b1.
+        tree.fork(&genesis, a).unwrap();
+        tree.fork(&genesis, b).unwrap();
+        tree.fork(&a, a1).unwrap();
+        tree.fork(&a, a2).unwrap();
+        tree.fork(&b, b1).unwrap();
+        assert_eq!(tree.fork(&a, b), Err(ForkError::DuplicateBlock(b)));
+        assert_eq!(
+            tree.fork(&[0xff; 32], [0xfe; 32]),
+            Err(ForkError::UnknownBlock([0xff; 32]))
+        );
+        assert_eq!(tree.live_blocks(), 6);
+        assert_eq!(tree.parent(&a1), Some(&a));
+
+        // Identical states share all their nodes.
+        assert_eq!(tree.node_count(), 256);
+        tree.set(&a, &[2; 32], [0x22; 32]).unwrap();
+        tree.set(&a1, &[3; 32], [0x33; 32]).unwrap();
+        tree.set(&b1, &[1; 32], [0; 32]).unwrap();
+        tree.set(&b, &[4; 32], [0x44; 32]).unwrap();
+        assert_eq!(tree.get(&a, &[2; 32]), Some([0x22; 32]));
+        assert_eq!(tree.get(&a1, &[2; 32]), Some([0; 32]));
+        assert_eq!(tree.get(&b, &[1; 32]), Some([0x11; 32]));
+        assert_eq!(tree.get(&b1, &[1; 32]), Some([0; 32]));
+
+        // Proofs against the root of each branch.
+        let mut a1_map = SmtMap256::new();
+        a1_map.set(&[1; 32], [0x11; 32]);
+        a1_map.set(&[3; 32], [0x33; 32]);
+        assert_eq!(tree.merkle_root(&a1), Some(a1_map.merkle_root()));
+        for block in [genesis, a, b, a1, a2, b1] {
+            let root = tree.merkle_root(&block).unwrap();
+            for key in [[1; 32], [2; 32], [3; 32]] {
+                let (value, proof) = tree.get_with_proof(&block, &key).unwrap();
+                assert!(check_merkle_proof(root, &key, &value, &proof));
+            }
+        }
+
+        // Finalizing `a` discards `b`, `b1` and the former finalized block, and the nodes only `b`
+        // has (`a2` still has the state of the former finalized block).
+        let node_count = tree.node_count();
+        tree.finalize(&a).unwrap();
+        assert_eq!(tree.finalized(), &a);
+        assert_eq!(tree.parent(&a), None);
+        assert_eq!(tree.live_blocks(), 3);
+        for block in [genesis, b, b1] {
+            assert!(!tree.contains(&block));
+            assert_eq!(tree.merkle_root(&block), None);
+            assert_eq!(
+                tree.set(&block, &[1; 32], [1; 32]),
+                Err(ForkError::UnknownBlock(block))
+            );
+        }
+        assert!(tree.node_count() < node_count);
+        assert_eq!(tree.merkle_root(&a1), Some(a1_map.merkle_root()));
+
+        tree.finalize(&a2).unwrap();
+        assert_eq!(tree.live_blocks(), 1);
+        assert_eq!(tree.get(&a2, &[1; 32]), Some([0x11; 32]));
+        assert_eq!(tree.node_count(), 256);
+    }
+}
@@ -0,0 +1,58 @@
+//! Hash functions of the inner nodes of an `SmtMap256`.
+//!
+//! A map is generic over a `Hasher`, which merges the hashes of two sibling nodes into the hash of
+//! their parent. The leaves always hash to their values, and the default hashes follow from the
+//! hasher, with the default leaf being zero. `Keccak256` is the default. With the `poseidon`
+//! feature, `poseidon::Poseidon` hashes in the BN254 scalar field instead.
+
+use core::fmt;
+
+use crate::{merge_hashes, Hash256, DEFAULT_HASHES};
+
+/// Hash function of the inner nodes of an `SmtMap256`.
+pub trait Hasher {
+    /// Returns the hash of a node with the given child hashes, or `None` if the hasher does not
+    /// accept them.
+    fn merge(left: &Hash256, right: &Hash256) -> Option<Hash256>;
+
+    /// Whether a key or a value can be set in a map using this hasher. Merging the hashes of
+    /// accepted values and of inner nodes never fails.
+    fn accepts(word: &[u8; 32]) -> bool {
+        let _ = word;
+        true
+    }
+
+    /// Returns the hashes of the default subtrees of height 0, 1, ..., 256.
+    fn default_hashes() -> &'static [Hash256; 257];
+}
+
+/// A key or a value which the hasher of a map does not accept (see `Hasher::accepts`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NotAccepted(pub [u8; 32]);
+
+impl fmt::Display for NotAccepted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("word not accepted by the hasher: 0x")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NotAccepted {}
+
+/// The keccak-256 of the concatenated child hashes.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Keccak256;
+
+impl Hasher for Keccak256 {
+    fn merge(left: &Hash256, right: &Hash256) -> Option<Hash256> {
+        Some(merge_hashes(left, right))
+    }
+
+    fn default_hashes() -> &'static [Hash256; 257] {
+        &DEFAULT_HASHES
+    }
+}
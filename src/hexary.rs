@@ -0,0 +1,358 @@
+//! 16-ary variant of `SmtMap256`, with 64 levels instead of 256.
+//!
+//! Every inner node has 16 children, and the path from the root to the leaf of a key is the
+//! sequence of the nibbles of the key, each nibble being the index of the child to descend into.
+//! The nibbles are taken in the order of the bits of `NodeIndex`: the low nibble of each byte
+//! first. A leaf hashes to its value, and an inner node to the keccak-256 of the concatenated
+//! hashes of its 16 children.
+//!
+//! A proof of a key lists the 15 siblings of the node on the path at each level. Levels where all
+//! the 15 siblings are default are left out and marked in a bitmap, so a proof has at most 64
+//! groups of 15 hashes, against at most 256 hashes for an `SmtMap256`. Writes merge 64 times
+//! instead of 256, but each merge hashes 16 children instead of 2.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::{Hash256, Key, Value};
+
+/// The number of levels below the root.
+const HEIGHT: usize = 64;
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_HEX_HASHES: [Hash256; HEIGHT + 1] = {
+        // The element at index `i` is the hash of a subtree with `16^i` default leaves.
+        let mut hashes = [[0; 32]; HEIGHT + 1];
+        for i in 1..=HEIGHT {
+            hashes[i] = merge_children(&[hashes[i - 1]; 16]);
+        }
+        hashes
+    };
+}
+
+/// Merkle proof of a certain triple (root, key, value) in an `SmtMap256Hex`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HexMerkleProof {
+    /// Whether the sibling groups along the path to the root have non-default hashes, the bit `i`
+    /// (the least significant bit of byte `i / 8` first) being the group at height `i`.
+    pub bitmap: [u8; 8],
+
+    /// The sibling groups marked in the bitmap, from the leaf up. Each group has the hashes of the
+    /// 15 siblings in the order of their indices.
+    pub groups: Vec<[Hash256; 15]>,
+}
+
+impl HexMerkleProof {
+    /// Returns the root of the `SmtMap256Hex` in which this proves the key-value pair, or `None`
+    /// if the number of groups does not match the bitmap.
+    pub fn compute_root(&self, key: &Key, value: &Value) -> Option<Hash256> {
+        let mut hash = *value;
+        let mut iter = self.groups.iter();
+        for height in 0..HEIGHT {
+            let nibble = get_nibble(key, HEIGHT - 1 - height);
+            let default_group = [DEFAULT_HEX_HASHES[height]; 15];
+            let group = if self.bitmap[height / 8] & (1 << (height % 8)) == 0 {
+                &default_group
+            } else {
+                iter.next()?
+            };
+
+            let mut children = [[0; 32]; 16];
+            children[..nibble].copy_from_slice(&group[..nibble]);
+            children[nibble] = hash;
+            children[nibble + 1..].copy_from_slice(&group[nibble..]);
+            hash = merge_children(&children);
+        }
+
+        if iter.next().is_some() {
+            return None;
+        }
+        Some(hash)
+    }
+}
+
+/// Index of a node of an `SmtMap256Hex`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct HexNodeIndex {
+    // The first `depth` nibbles of the key, and zeros after.
+    nibble_path: [u8; 32],
+
+    // The root has depth of 0, and the leaves have depth of 64.
+    depth: usize,
+}
+
+impl HexNodeIndex {
+    fn leaf(key: Key) -> Self {
+        Self {
+            nibble_path: key,
+            depth: HEIGHT,
+        }
+    }
+
+    fn root() -> Self {
+        Self {
+            nibble_path: [0; 32],
+            depth: 0,
+        }
+    }
+
+    /// Returns the index of this node among its siblings. Panics if `self` is the root.
+    fn nibble(&self) -> usize {
+        get_nibble(&self.nibble_path, self.depth - 1)
+    }
+
+    /// Change `self` to the index of its parent node. Panics if `self` is the root.
+    fn move_up(&mut self) {
+        assert!(self.depth > 0, "Cannot move up from the root");
+        set_nibble(&mut self.nibble_path, self.depth - 1, 0);
+        self.depth -= 1;
+    }
+
+    /// Returns the index of the `nibble`-th child of this node. Panics if `self` is a leaf.
+    fn child(&self, nibble: usize) -> Self {
+        assert!(self.depth < HEIGHT, "A leaf has no children");
+        let mut result = Self {
+            nibble_path: self.nibble_path,
+            depth: self.depth + 1,
+        };
+        set_nibble(&mut result.nibble_path, self.depth, nibble);
+        result
+    }
+}
+
+/// Sparse Merkle Tree Map with a branching factor of 16, from 256-bit keys to 256-bit values.
+/// Initially every key has a default value of zero.
+#[derive(Clone, Default)]
+pub struct SmtMap256Hex {
+    kvs: BTreeMap<Key, Value>,
+
+    // Hash values of both leaf and inner nodes.
+    hashes: BTreeMap<HexNodeIndex, Hash256>,
+}
+
+impl SmtMap256Hex {
+    /// Returns a new map where all keys have the default value (zero).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of a key. Returns the old value of the key.
+    pub fn set(&mut self, key: &Key, value: Value) -> Value {
+        let mut index = HexNodeIndex::leaf(*key);
+        let mut hash = value;
+        self.update_hash(&index, &hash);
+
+        while index.depth > 0 {
+            let nibble = index.nibble();
+            index.move_up();
+            let mut children = [[0; 32]; 16];
+            for (i, child) in children.iter_mut().enumerate() {
+                *child = if i == nibble {
+                    hash
+                } else {
+                    *self.get_hash(&index.child(i))
+                };
+            }
+            hash = merge_children(&children);
+            self.update_hash(&index, &hash);
+        }
+
+        if value == [0; 32] {
+            self.kvs.remove(key).unwrap_or([0; 32])
+        } else {
+            self.kvs.insert(*key, value).unwrap_or([0; 32])
+        }
+    }
+
+    /// Returns a reference to the value of a key.
+    pub fn get(&self, key: &Key) -> &Value {
+        self.kvs.get(key).unwrap_or(&[0; 32])
+    }
+
+    /// Returns a reference to the value of the key with merkle proof.
+    pub fn get_with_proof(&self, key: &Key) -> (&Value, HexMerkleProof) {
+        let mut bitmap = [0; 8];
+        let mut groups = Vec::new();
+        let mut index = HexNodeIndex::leaf(*key);
+        for height in 0..HEIGHT {
+            let nibble = index.nibble();
+            index.move_up();
+            let siblings = (0..16).filter(|i| *i != nibble).map(|i| index.child(i));
+            if siblings
+                .clone()
+                .any(|sibling| self.hashes.contains_key(&sibling))
+            {
+                bitmap[height / 8] |= 1 << (height % 8);
+                let mut group = [[0; 32]; 15];
+                for (hash, sibling) in group.iter_mut().zip(siblings) {
+                    *hash = *self.get_hash(&sibling);
+                }
+                groups.push(group);
+            }
+        }
+        (self.get(key), HexMerkleProof { bitmap, groups })
+    }
+
+    /// Returns the merkle root of this tree.
+    pub fn merkle_root(&self) -> &Hash256 {
+        self.get_hash(&HexNodeIndex::root())
+    }
+
+    /// Returns the number of keys with non-default values.
+    pub fn len(&self) -> usize {
+        self.kvs.len()
+    }
+
+    /// Whether every key has the default value.
+    pub fn is_empty(&self) -> bool {
+        self.kvs.is_empty()
+    }
+
+    /// Returns the number of nodes (leaves included) with non-default hashes.
+    pub fn node_count(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Check the merkle proof of a key-value pair in this map. Returns whether the proof is valid.
+    pub fn check_merkle_proof(&self, key: &Key, value: &Value, proof: &HexMerkleProof) -> bool {
+        check_hex_merkle_proof(self.merkle_root(), key, value, proof)
+    }
+
+    fn get_hash(&self, index: &HexNodeIndex) -> &Hash256 {
+        self.hashes
+            .get(index)
+            .unwrap_or(&(*DEFAULT_HEX_HASHES)[HEIGHT - index.depth])
+    }
+
+    fn update_hash(&mut self, index: &HexNodeIndex, hash: &Hash256) {
+        if (*DEFAULT_HEX_HASHES)[HEIGHT - index.depth] == *hash {
+            self.hashes.remove(index);
+        } else {
+            self.hashes.insert(index.clone(), *hash);
+        }
+    }
+}
+
+/// Check the merkle proof of a key-value pair in an `SmtMap256Hex` (specified by its merkle
+/// root). Returns whether the proof is valid.
+pub fn check_hex_merkle_proof(
+    merkle_root: &Hash256,
+    key: &Key,
+    value: &Value,
+    proof: &HexMerkleProof,
+) -> bool {
+    proof
+        .compute_root(key, value)
+        .is_some_and(|root| root == *merkle_root)
+}
+
+fn merge_children(children: &[Hash256; 16]) -> Hash256 {
+    let mut hasher = tiny_keccak::Keccak::new_keccak256();
+    for child in children {
+        hasher.update(child);
+    }
+    let mut merged = [0; 32];
+    hasher.finalize(&mut merged);
+    merged
+}
+
+// The `index`-th nibble, the low nibble of each byte first.
+fn get_nibble(b: &[u8; 32], index: usize) -> usize {
+    ((b[index / 2] >> (4 * (index % 2))) & 0xf) as usize
+}
+
+fn set_nibble(b: &mut [u8; 32], index: usize, nibble: usize) {
+    let shift = 4 * (index % 2);
+    b[index / 2] = (b[index / 2] & !(0xf << shift)) | ((nibble as u8) << shift);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SmtMap256;
+
+    #[test]
+    fn test_smt_map_256_hex() {
+        let mut smt = SmtMap256Hex::new();
+        assert_eq!(*smt.merkle_root(), DEFAULT_HEX_HASHES[HEIGHT]);
+        let (value, proof) = smt.get_with_proof(&[0; 32]);
+        assert_eq!(proof.bitmap, [0; 8]);
+        assert!(proof.groups.is_empty());
+        assert!(smt.check_merkle_proof(&[0; 32], value, &proof));
+
+        // The last nibble of a key is the high nibble of its last byte, so keys 0x00..00 and
+        // 0x00..10 are sibling leaves.
+        let (key0, key1) = ([0; 32], [1; 32]);
+        let mut key2 = [0; 32];
+        key2[31] = 0x10;
+        smt.set(&key0, [0xaa; 32]);
+        smt.set(&key2, [0xbb; 32]);
+        let mut hash = merge_children(&{
+            let mut children = [[0; 32]; 16];
+            children[0] = [0xaa; 32];
+            children[1] = [0xbb; 32];
+            children
+        });
+        for height in 1..HEIGHT {
+            let mut children = [DEFAULT_HEX_HASHES[height]; 16];
+            children[0] = hash;
+            hash = merge_children(&children);
+        }
+        assert_eq!(*smt.merkle_root(), hash);
+
+        let (value, proof) = smt.get_with_proof(&key2);
+        assert_eq!(*value, [0xbb; 32]);
+        assert_eq!(proof.bitmap, [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(proof.groups[0][0], [0xaa; 32]);
+        assert!(smt.check_merkle_proof(&key2, value, &proof));
+        assert!(!smt.check_merkle_proof(&key2, &[0xaa; 32], &proof));
+        assert!(!smt.check_merkle_proof(&key1, value, &proof));
+
+        let mut malformed = proof.clone();
+        malformed.groups.push([[0; 32]; 15]);
+        assert_eq!(malformed.compute_root(&key2, value), None);
+        malformed.groups.clear();
+        assert_eq!(malformed.compute_root(&key2, value), None);
+
+        assert_eq!(smt.set(&key1, [0xcc; 32]), [0; 32]);
+        assert_eq!(smt.len(), 3);
+        for key in [key0, key1, key2, [0xff; 32]] {
+            let (value, proof) = smt.get_with_proof(&key);
+            assert!(check_hex_merkle_proof(
+                smt.merkle_root(),
+                &key,
+                value,
+                &proof
+            ));
+        }
+
+        // Resetting every key gives back the empty tree.
+        for key in [key1, key0, key2] {
+            smt.set(&key, [0; 32]);
+        }
+        assert!(smt.is_empty());
+        assert_eq!(smt.node_count(), 0);
+        assert_eq!(*smt.merkle_root(), DEFAULT_HEX_HASHES[HEIGHT]);
+    }
+
+    #[test]
+    fn test_proof_size() {
+        let mut hex_smt = SmtMap256Hex::new();
+        let mut smt = SmtMap256::new();
+        for i in 0..=255_u8 {
+            let key = [i.wrapping_mul(113); 32];
+            hex_smt.set(&key, [1; 32]);
+            smt.set(&key, [1; 32]);
+        }
+
+        // The first byte tells the 256 keys apart, so only the siblings at the two top levels
+        // (eight in a binary tree) are not default.
+        let key = [113; 32];
+        let (value, proof) = hex_smt.get_with_proof(&key);
+        assert!(hex_smt.check_merkle_proof(&key, value, &proof));
+        assert_eq!(proof.groups.len(), 2);
+        let (_, binary_proof) = smt.get_with_proof(&key);
+        assert_eq!(binary_proof.hashes.len(), 8);
+        assert!(hex_smt.node_count() < smt.node_count());
+    }
+}
@@ -0,0 +1,380 @@
+//! Indexed merkle tree: a shallow append-only tree whose leaves form a linked list sorted by
+//! value, for compact non-membership proofs.
+//!
+//! The tree has a fixed depth of `INDEXED_TREE_DEPTH`, and its leaves are filled from left to
+//! right. Each leaf holds a value and points to the leaf with the next larger value, where a
+//! `next_value` of zero stands for the end of the list. Leaf 0 is a sentinel with value zero,
+//! so zero can never be inserted and every other value has a "low leaf": the leaf with the
+//! largest value below it.
+//!
+//! A value is absent if and only if its low leaf skips over it, so the merkle proof of the low
+//! leaf alone proves the absence. Values are compared as big-endian integers.
+//!
+//! The hash of a leaf is the keccak-256 of `value || next_value || next_index`, with the index as
+//! a 32-byte big-endian integer. Unused leaves hash to zero, and inner nodes hash like in
+//! `SmtMap256`, so the default subtrees are those of `DEFAULT_HASHES`.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::{merge_hashes, Hash256, Key, DEFAULT_HASHES};
+
+/// Number of levels below the root. The tree holds up to `2^INDEXED_TREE_DEPTH` leaves.
+pub const INDEXED_TREE_DEPTH: usize = 32;
+
+/// A leaf of an indexed merkle tree.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IndexedLeaf {
+    pub value: Key,
+
+    /// The next larger value in the tree, or zero if `value` is the largest.
+    pub next_value: Key,
+
+    /// The index of the leaf holding `next_value`, or zero if `value` is the largest.
+    pub next_index: u64,
+}
+
+impl IndexedLeaf {
+    /// Returns the hash of the leaf.
+    pub fn hash(&self) -> Hash256 {
+        let mut index = [0; 32];
+        index[24..].copy_from_slice(&self.next_index.to_be_bytes());
+        let mut hasher = tiny_keccak::Keccak::new_keccak256();
+        hasher.update(&self.value);
+        hasher.update(&self.next_value);
+        hasher.update(&index);
+        let mut hash = [0; 32];
+        hasher.finalize(&mut hash);
+        hash
+    }
+
+    /// Whether `value` is strictly between the value of this leaf and the next one.
+    pub fn skips(&self, value: &Key) -> bool {
+        self.value < *value && (self.next_value == [0; 32] || *value < self.next_value)
+    }
+}
+
+/// Merkle proof of a leaf in an indexed merkle tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IndexedMerkleProof {
+    pub leaf: IndexedLeaf,
+
+    /// The position of the leaf, from the left.
+    pub index: u64,
+
+    /// The hashes of the `INDEXED_TREE_DEPTH` siblings along the path, from the sibling of the
+    /// leaf up to the sibling of the child of the root.
+    pub siblings: Vec<Hash256>,
+}
+
+impl IndexedMerkleProof {
+    /// Returns the root of the tree in which the proof proves the leaf, or `None` if the proof is
+    /// malformed.
+    fn compute_root(&self) -> Option<Hash256> {
+        if self.siblings.len() != INDEXED_TREE_DEPTH || self.index >> INDEXED_TREE_DEPTH != 0 {
+            return None;
+        }
+        let mut hash = self.leaf.hash();
+        for (height, sibling) in self.siblings.iter().enumerate() {
+            hash = if self.index >> height & 1 == 0 {
+                merge_hashes(&hash, sibling)
+            } else {
+                merge_hashes(sibling, &hash)
+            };
+        }
+        Some(hash)
+    }
+}
+
+/// Reason for rejecting an insertion.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertError {
+    /// Zero is the value of the sentinel leaf.
+    Zero,
+
+    AlreadyPresent,
+
+    /// All the `2^INDEXED_TREE_DEPTH` leaves are used.
+    Full,
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InsertError::Zero => "zero cannot be inserted",
+            InsertError::AlreadyPresent => "value already present",
+            InsertError::Full => "tree is full",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InsertError {}
+
+/// Indexed merkle tree holding a set of non-zero 256-bit values.
+#[derive(Clone)]
+pub struct IndexedMerkleTree {
+    leaves: Vec<IndexedLeaf>,
+
+    // The index of the leaf of every value, the sentinel included.
+    indices: BTreeMap<Key, u64>,
+
+    // The hashes of the used nodes at each height, from the leaves up to the root. Other nodes
+    // have default hashes.
+    levels: Vec<Vec<Hash256>>,
+}
+
+impl Default for IndexedMerkleTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IndexedMerkleTree {
+    /// Returns a tree holding only the sentinel leaf.
+    pub fn new() -> Self {
+        let mut tree = Self {
+            leaves: Vec::new(),
+            indices: BTreeMap::new(),
+            levels: alloc::vec![Vec::new(); INDEXED_TREE_DEPTH + 1],
+        };
+        tree.push_leaf(IndexedLeaf {
+            value: [0; 32],
+            next_value: [0; 32],
+            next_index: 0,
+        });
+        tree
+    }
+
+    /// Inserts a value, and returns the index of its leaf.
+    pub fn insert(&mut self, value: &Key) -> Result<u64, InsertError> {
+        if *value == [0; 32] {
+            return Err(InsertError::Zero);
+        }
+        if self.indices.contains_key(value) {
+            return Err(InsertError::AlreadyPresent);
+        }
+        let index = self.leaves.len() as u64;
+        if index >> INDEXED_TREE_DEPTH != 0 {
+            return Err(InsertError::Full);
+        }
+
+        let low_index = self.low_index(value);
+        let low_leaf = self.leaves[low_index as usize];
+        self.update_leaf(
+            low_index,
+            IndexedLeaf {
+                next_value: *value,
+                next_index: index,
+                ..low_leaf
+            },
+        );
+        self.push_leaf(IndexedLeaf {
+            value: *value,
+            next_value: low_leaf.next_value,
+            next_index: low_leaf.next_index,
+        });
+        Ok(index)
+    }
+
+    /// Whether the tree holds the value. The sentinel value zero is always held.
+    pub fn contains(&self, value: &Key) -> bool {
+        self.indices.contains_key(value)
+    }
+
+    /// Returns the number of leaves, the sentinel included.
+    pub fn leaf_count(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    /// Returns the leaf at `index`, or `None` if it is unused.
+    pub fn leaf(&self, index: u64) -> Option<&IndexedLeaf> {
+        self.leaves.get(usize::try_from(index).ok()?)
+    }
+
+    /// Returns the merkle root of the tree.
+    pub fn merkle_root(&self) -> &Hash256 {
+        self.node_hash(INDEXED_TREE_DEPTH, 0)
+    }
+
+    /// Returns the merkle proof of the leaf holding `value`, or `None` if the value is absent.
+    pub fn prove_membership(&self, value: &Key) -> Option<IndexedMerkleProof> {
+        self.indices.get(value).map(|index| self.prove(*index))
+    }
+
+    /// Returns the merkle proof of the low leaf of `value`, or `None` if the value is present.
+    pub fn prove_absence(&self, value: &Key) -> Option<IndexedMerkleProof> {
+        if self.contains(value) {
+            return None;
+        }
+        Some(self.prove(self.low_index(value)))
+    }
+
+    fn prove(&self, index: u64) -> IndexedMerkleProof {
+        let siblings = (0..INDEXED_TREE_DEPTH)
+            .map(|height| *self.node_hash(height, (index >> height) ^ 1))
+            .collect();
+        IndexedMerkleProof {
+            leaf: self.leaves[index as usize],
+            index,
+            siblings,
+        }
+    }
+
+    // Returns the index of the leaf with the largest value below `value`.
+    fn low_index(&self, value: &Key) -> u64 {
+        *self.indices.range(..*value).next_back().unwrap().1
+    }
+
+    fn node_hash(&self, height: usize, position: u64) -> &Hash256 {
+        self.levels[height]
+            .get(position as usize)
+            .unwrap_or(&(*DEFAULT_HASHES)[height])
+    }
+
+    fn push_leaf(&mut self, leaf: IndexedLeaf) {
+        let index = self.leaves.len() as u64;
+        self.indices.insert(leaf.value, index);
+        self.leaves.push(leaf);
+        // The new nodes are all on the path of the new leaf, and are set by `update_leaf`.
+        for (height, level) in self.levels.iter_mut().enumerate() {
+            level.resize(((self.leaves.len() - 1) >> height) + 1, [0; 32]);
+        }
+        self.update_leaf(index, leaf);
+    }
+
+    fn update_leaf(&mut self, index: u64, leaf: IndexedLeaf) {
+        self.leaves[index as usize] = leaf;
+        self.levels[0][index as usize] = leaf.hash();
+        let mut position = index;
+        for height in 1..=INDEXED_TREE_DEPTH {
+            let left = *self.node_hash(height - 1, position & !1);
+            let right = *self.node_hash(height - 1, position | 1);
+            position >>= 1;
+            self.levels[height][position as usize] = merge_hashes(&left, &right);
+        }
+    }
+}
+
+/// Checks the proof that `value` is in the indexed merkle tree with the given root.
+pub fn check_membership(merkle_root: &Hash256, value: &Key, proof: &IndexedMerkleProof) -> bool {
+    proof.leaf.value == *value && proof.compute_root() == Some(*merkle_root)
+}
+
+/// Checks the proof that `value` is not in the indexed merkle tree with the given root, which is
+/// the merkle proof of its low leaf.
+pub fn check_absence(merkle_root: &Hash256, value: &Key, proof: &IndexedMerkleProof) -> bool {
+    proof.leaf.skips(value) && proof.compute_root() == Some(*merkle_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(n: u8) -> Key {
+        let mut value = [0; 32];
+        value[0] = n;
+        value
+    }
+
+    // Recomputes the root from the leaves alone.
+    fn naive_root(tree: &IndexedMerkleTree) -> Hash256 {
+        let mut hashes: Vec<Hash256> = tree.leaves.iter().map(IndexedLeaf::hash).collect();
+        for height in 0..INDEXED_TREE_DEPTH {
+            if hashes.len() % 2 == 1 {
+                hashes.push((*DEFAULT_HASHES)[height]);
+            }
+            hashes = hashes
+                .chunks(2)
+                .map(|pair| merge_hashes(&pair[0], &pair[1]))
+                .collect();
+        }
+        hashes[0]
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut tree = IndexedMerkleTree::new();
+        assert_eq!(tree.leaf_count(), 1);
+        assert_eq!(*tree.merkle_root(), naive_root(&tree));
+
+        for (i, n) in [30, 10, 20, 40, 5].iter().enumerate() {
+            assert_eq!(tree.insert(&value(*n)), Ok(i as u64 + 1));
+            assert_eq!(*tree.merkle_root(), naive_root(&tree));
+        }
+        assert_eq!(tree.insert(&value(20)), Err(InsertError::AlreadyPresent));
+        assert_eq!(tree.insert(&[0; 32]), Err(InsertError::Zero));
+        assert_eq!(tree.leaf_count(), 6);
+
+        // Walking the linked list from the sentinel visits the values in order.
+        let mut values = Vec::new();
+        let mut leaf = tree.leaf(0).unwrap();
+        while leaf.next_value != [0; 32] {
+            let next = tree.leaf(leaf.next_index).unwrap();
+            assert_eq!(next.value, leaf.next_value);
+            values.push(next.value);
+            leaf = next;
+        }
+        assert_eq!(
+            values,
+            [5, 10, 20, 30, 40]
+                .iter()
+                .map(|n| value(*n))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(leaf.next_index, 0);
+    }
+
+    #[test]
+    fn test_proofs() {
+        let mut tree = IndexedMerkleTree::new();
+        let empty_root = *tree.merkle_root();
+        let proof = tree.prove_absence(&value(1)).unwrap();
+        assert_eq!(proof.index, 0);
+        assert!(check_absence(&empty_root, &value(1), &proof));
+
+        for n in [30, 10, 20] {
+            tree.insert(&value(n)).unwrap();
+        }
+        let root = *tree.merkle_root();
+
+        for n in [10, 20, 30] {
+            let proof = tree.prove_membership(&value(n)).unwrap();
+            assert!(check_membership(&root, &value(n), &proof));
+            assert!(!check_absence(&root, &value(n), &proof));
+            assert!(tree.prove_absence(&value(n)).is_none());
+        }
+        assert!(tree.prove_membership(&value(15)).is_none());
+
+        // Below the minimum, between two values, and above the maximum.
+        for (n, low) in [(5, 0), (15, 10), (25, 20), (255, 30)] {
+            let proof = tree.prove_absence(&value(n)).unwrap();
+            assert_eq!(
+                proof.leaf.value,
+                if low == 0 { [0; 32] } else { value(low) }
+            );
+            assert!(check_absence(&root, &value(n), &proof));
+            assert!(!check_membership(&root, &value(n), &proof));
+            assert!(!check_absence(&empty_root, &value(n), &proof));
+        }
+
+        // A low leaf which does not skip the value.
+        let proof = tree.prove_absence(&value(15)).unwrap();
+        assert!(!check_absence(&root, &value(25), &proof));
+
+        // A forged low leaf.
+        let mut forged = proof.clone();
+        forged.leaf.next_value = value(40);
+        assert!(!check_absence(&root, &value(25), &forged));
+
+        // Malformed proofs.
+        let mut malformed = proof.clone();
+        malformed.siblings.pop();
+        assert!(!check_absence(&root, &value(15), &malformed));
+        let mut malformed = proof;
+        malformed.index |= 1 << INDEXED_TREE_DEPTH;
+        assert!(!check_absence(&root, &value(15), &malformed));
+    }
+}
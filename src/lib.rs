@@ -1,27 +1,85 @@
 #![no_std]
 
-#![allow(stable_features)]
-#![feature(alloc)]
-
-#[cfg(not(test))]
+#[cfg(all(feature = "alloc", not(test)))]
 extern crate alloc;
 
-#[cfg(test)]
+#[cfg(all(feature = "alloc", test))]
 #[macro_use]
 extern crate alloc;
 
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "alloc")]
 use alloc::collections::btree_map::BTreeMap;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::marker::PhantomData;
 
-mod bit_op;
+#[cfg(feature = "alloc")]
+use crate::hasher::{Hasher, Keccak256, NotAccepted};
+#[cfg(feature = "alloc")]
+use crate::order::{BitOrder, LsbFirst};
+#[cfg(feature = "alloc")]
+use crate::store::{BTreeStorage, MapStore, Storage};
 
-#[cfg(test)]
+mod bit_op;
+#[cfg(feature = "alloc")]
+pub mod chunk;
+#[cfg(feature = "alloc")]
+pub mod circuit;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "alloc")]
+pub mod forest;
+#[cfg(feature = "alloc")]
+pub mod fork;
+#[cfg(feature = "alloc")]
+pub mod hasher;
+#[cfg(feature = "alloc")]
+pub mod hexary;
+#[cfg(feature = "alloc")]
+pub mod indexed;
+#[cfg(feature = "alloc")]
+pub mod nested;
+#[cfg(feature = "alloc")]
+pub mod order;
+#[cfg(feature = "std")]
+pub mod persist;
+#[cfg(feature = "poseidon")]
+pub mod poseidon;
+#[cfg(feature = "alloc")]
+pub mod profile;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "alloc")]
+pub mod set;
+#[cfg(feature = "alloc")]
+pub mod snapshot;
+#[cfg(feature = "alloc")]
+pub mod store;
+#[cfg(feature = "alloc")]
+pub mod sum;
+#[cfg(feature = "alloc")]
+pub mod sync;
+#[cfg(feature = "server")]
+pub(crate) mod text;
+#[cfg(feature = "alloc")]
+pub mod trace;
+#[cfg(feature = "alloc")]
+pub mod versioned;
+
+#[cfg(all(test, feature = "alloc"))]
 mod tests;
 
 pub type Key = [u8; 32];
 pub type Value = [u8; 32];
 pub type Hash256 = [u8; 32];
 
+#[cfg(feature = "alloc")]
 lazy_static::lazy_static! {
     static ref DEFAULT_HASHES: [Hash256; 257] = {
         // The element at index `i` is the hash of a subtree with `2^i` default nodes.
@@ -34,8 +92,12 @@ lazy_static::lazy_static! {
 }
 
 /// Index of a node in a Sparse Merkle Tree.
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-struct TreeNodeIndex {
+///
+/// A node is given by its depth and the path from the root to it. The path is laid out as the keys
+/// of `SmtMap256`s with the default bit order (`order::LsbFirst`), so the leaf of a key is
+/// `NodeIndex::leaf(key)`. For other bit orders, reorder the key first (`BitOrder::reorder`).
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct NodeIndex {
     // The path starts from the first bit (the least significant bit of the first byte), and ends at
     // the `depth`-th bit. Bit 0 means left, and bit 1 means right. Bits beyond the `depth`-th bit
     // are irrelevant, and are always zeros.
@@ -45,9 +107,9 @@ struct TreeNodeIndex {
     depth: usize,
 }
 
-impl TreeNodeIndex {
-    /// Get a new TreeNodeIndex of the leaf corresponding to the given key.
-    fn leaf(key: Key) -> Self {
+impl NodeIndex {
+    /// Get a new NodeIndex of the leaf corresponding to the given key.
+    pub fn leaf(key: Key) -> Self {
         Self {
             bit_path: key,
             depth: 256,
@@ -55,25 +117,35 @@ impl TreeNodeIndex {
     }
 
     /// Index of the root.
-    fn root() -> Self {
+    pub fn root() -> Self {
         Self {
             bit_path: [0; 32],
             depth: 0,
         }
     }
 
+    /// The depth of this node. The root has depth of 0, and the leaves have depth of 256.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The path from the root to this node: its first `depth` bits, and zeros after.
+    pub fn bit_path(&self) -> &[u8; 32] {
+        &self.bit_path
+    }
+
     /// Whether this is the root.
-    fn is_root(&self) -> bool {
+    pub fn is_root(&self) -> bool {
         self.depth == 0
     }
 
     /// Whether this is a left subnode.
-    fn is_left(&self) -> bool {
+    pub fn is_left(&self) -> bool {
         self.depth > 0 && !bit_op::get_bit(&self.bit_path, self.depth - 1)
     }
 
     /// Returns the index of the sibling of this node. Returns `None` if `self` is the root.
-    fn sibling(&self) -> Option<TreeNodeIndex> {
+    pub fn sibling(&self) -> Option<NodeIndex> {
         if self.is_root() {
             return None;
         }
@@ -83,16 +155,67 @@ impl TreeNodeIndex {
         Some(result)
     }
 
+    /// Returns the index of the parent of this node. Returns `None` if `self` is the root.
+    pub fn parent(&self) -> Option<NodeIndex> {
+        if self.is_root() {
+            return None;
+        }
+
+        let mut result = self.clone();
+        result.move_up();
+        Some(result)
+    }
+
     /// Change `self` to the index of its parent node. Panics if `self` is the root.
     fn move_up(&mut self) {
         assert!(self.depth > 0, "Cannot move up from the root");
         bit_op::clear_bit(&mut self.bit_path, self.depth - 1);
         self.depth -= 1;
     }
+
+    /// Returns the index of the left child of this node. Panics if `self` is a leaf.
+    pub fn left_child(&self) -> NodeIndex {
+        assert!(self.depth < 256, "A leaf has no children");
+        Self {
+            bit_path: self.bit_path,
+            depth: self.depth + 1,
+        }
+    }
+
+    /// Returns the index of the right child of this node. Panics if `self` is a leaf.
+    pub fn right_child(&self) -> NodeIndex {
+        let mut result = self.left_child();
+        bit_op::set_bit(&mut result.bit_path, self.depth);
+        result
+    }
+
+    /// Returns an iterator over the ancestors of this node, from its parent up to the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = NodeIndex> {
+        core::iter::successors(self.parent(), NodeIndex::parent)
+    }
+
+    /// Returns the index of the deepest node which is both `self` or one of its ancestors and
+    /// `other` or one of its ancestors.
+    pub fn common_ancestor(&self, other: &NodeIndex) -> NodeIndex {
+        let depth = (0..self.depth.min(other.depth))
+            .find(|i| bit_op::get_bit(&self.bit_path, *i) != bit_op::get_bit(&other.bit_path, *i))
+            .unwrap_or_else(|| self.depth.min(other.depth));
+        let mut result = self.clone();
+        while result.depth > depth {
+            result.move_up();
+        }
+        result
+    }
+
+    /// Whether the leaf of `key` is this node or one of its descendants.
+    pub fn contains(&self, key: &Key) -> bool {
+        (0..self.depth).all(|i| bit_op::get_bit(&self.bit_path, i) == bit_op::get_bit(key, i))
+    }
 }
 
 /// Merkle proof of a certain triple (SMT-merkle-root, key, value).
-#[derive(PartialEq, Eq, Debug)]
+#[cfg(feature = "alloc")]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct MerkleProof {
     /// Whether the siblings along the path to the root are non-default hashes.
     pub bitmap: [u8; 32],
@@ -100,37 +223,125 @@ pub struct MerkleProof {
     pub hashes: Vec<Hash256>,
 }
 
+#[cfg(feature = "alloc")]
+impl MerkleProof {
+    /// Encodes the proof as the bitmap followed by the hashes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32 * (1 + self.hashes.len()));
+        bytes.extend_from_slice(&self.bitmap);
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash);
+        }
+        bytes
+    }
+
+    /// Decodes a proof encoded by `to_bytes`. Returns `None` if the length of `bytes` does not
+    /// match the number of bits set in the bitmap.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let proof = MerkleProofRef::from_bytes(bytes)?;
+        Some(Self {
+            bitmap: *proof.bitmap,
+            hashes: proof.hashes.to_vec(),
+        })
+    }
+}
+
+/// Borrowed form of `MerkleProof`, which can be checked with `check_merkle_proof_ref` without a
+/// heap.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MerkleProofRef<'a> {
+    /// Whether the siblings along the path to the root are non-default hashes.
+    pub bitmap: &'a [u8; 32],
+
+    pub hashes: &'a [Hash256],
+}
+
+impl<'a> MerkleProofRef<'a> {
+    /// Borrows a proof encoded by `MerkleProof::to_bytes`. Returns `None` if the length of `bytes`
+    /// does not match the number of bits set in the bitmap.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        let (words, rest) = bytes.as_chunks::<32>();
+        let (bitmap, hashes) = words.split_first()?;
+        let num_hashes = bitmap
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum::<usize>();
+        if !rest.is_empty() || hashes.len() != num_hashes {
+            return None;
+        }
+        Some(Self { bitmap, hashes })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<&'a MerkleProof> for MerkleProofRef<'a> {
+    fn from(proof: &'a MerkleProof) -> Self {
+        Self {
+            bitmap: &proof.bitmap,
+            hashes: &proof.hashes,
+        }
+    }
+}
+
 /// SmtMap256 is Sparse Merkle Tree Map from 256-bit keys to 256-bit values, and supports
 /// generating 256-bit merkle proofs. Initially every of the 2**256 possible keys has a default
 /// value of zero.
 ///
-/// Each leaf corresponds to a key-value pair. The key is the bit-path from the root to the leaf
-/// (see the documentation for TreeNodeIndex).
+/// Each leaf corresponds to a key-value pair. The key is the bit-path from the root to the leaf,
+/// with its bits in the order of `O` (see the `order` module).
 ///
 /// The hash of the leaf node is just the value of the corresponding key. The hash of an non-leaf
-/// node is calculated by hashing (using keccak-256) the concatenation of the hashes of its two
-/// sub-nodes.
+/// node is calculated by hashing (using keccak-256 by default, see the `hasher` module) the
+/// concatenation of the hashes of its two sub-nodes.
+///
+/// The key-values and hashes are kept in the maps of `S` (see the `store` module).
+#[cfg(feature = "alloc")]
 #[derive(Clone, Default)]
-pub struct SmtMap256 {
-    kvs: BTreeMap<Key, Value>,
+pub struct SmtMap256<S: Storage = BTreeStorage, H: Hasher = Keccak256, O: BitOrder = LsbFirst> {
+    kvs: S::Map<Key, Value>,
 
     // Hash values of both leaf and inner nodes.
-    hashes: BTreeMap<TreeNodeIndex, Hash256>,
+    hashes: S::Map<NodeIndex, Hash256>,
+
+    hasher: PhantomData<H>,
+
+    order: PhantomData<O>,
 }
 
+#[cfg(feature = "alloc")]
 impl SmtMap256 {
     /// Returns a new SMT-Map where all keys have the default value (zero).
     pub fn new() -> Self {
         Self {
             kvs: BTreeMap::new(),
             hashes: BTreeMap::new(),
+            hasher: PhantomData,
+            order: PhantomData,
         }
     }
+}
 
-    /// Sets the value of a key. Returns the old value of the key.
+#[cfg(feature = "alloc")]
+impl<S: Storage, H: Hasher, O: BitOrder> SmtMap256<S, H, O> {
+    /// Sets the value of a key. Returns the old value of the key. Panics if the hasher does not
+    /// accept the key or the value; see `try_set`.
     pub fn set(&mut self, key: &Key, value: Value) -> Value {
+        self.try_set(key, value)
+            .expect("The key or the value is not accepted by the hasher")
+    }
+
+    /// Sets the value of a key. Returns the old value of the key, or the key or the value if the
+    /// hasher does not accept it, in which case the map is left unchanged.
+    pub fn try_set(&mut self, key: &Key, value: Value) -> Result<Value, NotAccepted> {
+        if !H::accepts(key) {
+            return Err(NotAccepted(*key));
+        }
+        if !H::accepts(&value) {
+            return Err(NotAccepted(value));
+        }
+
         // Update the hash of the leaf.
-        let mut index = TreeNodeIndex::leaf(*key);
+        let mut index = NodeIndex::leaf(O::reorder(key));
         let mut hash: Hash256 = value;
         self.update_hash(&index, &hash);
 
@@ -139,15 +350,16 @@ impl SmtMap256 {
             let sibling_hash = self.get_hash(&index.sibling().unwrap());
 
             hash = if index.is_left() {
-                merge_hashes(&hash, &sibling_hash)
+                H::merge(&hash, sibling_hash)
             } else {
-                merge_hashes(&sibling_hash, &hash)
-            };
+                H::merge(sibling_hash, &hash)
+            }
+            .unwrap();
             index.move_up();
             self.update_hash(&index, &hash);
         }
 
-        self.kvs.insert(*key, value).unwrap_or([0; 32])
+        Ok(self.kvs.insert(*key, value).unwrap_or([0; 32]))
     }
 
     /// Returns a reference to the value of a key.
@@ -159,7 +371,7 @@ impl SmtMap256 {
     pub fn get_with_proof(&self, key: &Key) -> (&Value, MerkleProof) {
         let mut bitmap = [0_u8; 32];
         let mut sibling_hashes = Vec::new();
-        let mut index = TreeNodeIndex::leaf(*key);
+        let mut index = NodeIndex::leaf(O::reorder(key));
         for i in 0..256 {
             if let Some(sibling_hash) = self.hashes.get(&index.sibling().unwrap()) {
                 bit_op::set_bit(&mut bitmap, i);
@@ -170,31 +382,72 @@ impl SmtMap256 {
         (
             self.get(key),
             MerkleProof {
-                bitmap,
+                bitmap: O::reorder(&bitmap),
                 hashes: sibling_hashes,
             },
         )
     }
 
+    /// Returns an iterator over the keys with non-default values, in the order of the keys if the
+    /// storage keeps them in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.kvs.iter().filter(|(_, value)| **value != [0; 32])
+    }
+
+    /// Returns the number of keys with non-default values.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Whether every key has the default value.
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Returns the number of nodes (leaves included) with non-default hashes.
+    pub fn node_count(&self) -> usize {
+        self.hashes.len()
+    }
+
     /// Returns the merkle root of this Sparse Merkle Tree.
     pub fn merkle_root(&self) -> &Hash256 {
-        self.get_hash(&TreeNodeIndex::root())
+        self.get_hash(&NodeIndex::root())
     }
 
     /// Check the merkle proof of a key-value pair in this SMT-Map. Returns whether the proof is
     /// valid.
     pub fn check_merkle_proof(&self, key: &Key, value: &Value, proof: &MerkleProof) -> bool {
-        check_merkle_proof(self.merkle_root(), key, value, proof)
+        check_merkle_proof_with_order::<H, O>(self.merkle_root(), key, value, proof)
+    }
+
+    /// Returns an iterator over the keys whose values differ between `self` and `other`, yielding
+    /// `(key, value in self, value in other)`. Only subtrees whose hashes differ are visited, so
+    /// the cost is proportional to the size of the difference rather than the size of the maps.
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a SmtMap256<S, H, O>,
+    ) -> impl Iterator<Item = (Key, Value, Value)> + 'a {
+        Diff {
+            left: self,
+            right: other,
+            stack: alloc::vec![NodeIndex::root()],
+        }
     }
 
-    fn get_hash(&self, index: &TreeNodeIndex) -> &Hash256 {
+    /// Returns the hash of a node. The path of the node is laid out as the keys of a map with the
+    /// default bit order, whatever the bit order of this map.
+    pub fn node_hash(&self, index: &NodeIndex) -> &Hash256 {
+        self.get_hash(index)
+    }
+
+    fn get_hash(&self, index: &NodeIndex) -> &Hash256 {
         self.hashes
             .get(index)
-            .unwrap_or(&(*DEFAULT_HASHES)[256 - index.depth])
+            .unwrap_or(&H::default_hashes()[256 - index.depth])
     }
 
-    fn update_hash(&mut self, index: &TreeNodeIndex, hash: &Hash256) {
-        if (*DEFAULT_HASHES)[256 - index.depth] == *hash {
+    fn update_hash(&mut self, index: &NodeIndex, hash: &Hash256) {
+        if H::default_hashes()[256 - index.depth] == *hash {
             self.hashes.remove(index);
         } else {
             self.hashes.insert(index.clone(), *hash);
@@ -202,44 +455,157 @@ impl SmtMap256 {
     }
 }
 
+/// Iterator returned by `SmtMap256::diff`.
+#[cfg(feature = "alloc")]
+struct Diff<'a, S: Storage, H: Hasher, O: BitOrder> {
+    left: &'a SmtMap256<S, H, O>,
+    right: &'a SmtMap256<S, H, O>,
+
+    // Nodes still to be compared. The top of the stack is the left-most one in tree order.
+    stack: Vec<NodeIndex>,
+}
+
+#[cfg(feature = "alloc")]
+impl<S: Storage, H: Hasher, O: BitOrder> Iterator for Diff<'_, S, H, O> {
+    type Item = (Key, Value, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(index) = self.stack.pop() {
+            if self.left.get_hash(&index) == self.right.get_hash(&index) {
+                continue;
+            }
+            if index.depth == 256 {
+                let key = O::reorder(&index.bit_path);
+                return Some((key, *self.left.get(&key), *self.right.get(&key)));
+            }
+            self.stack.push(index.right_child());
+            self.stack.push(index.left_child());
+        }
+        None
+    }
+}
+
+/// Returns the hash of a subtree of the given height (the leaves having height of 0) where all
+/// keys have the default value, in an SMT-Map using keccak-256. See `Hasher::default_hashes` for
+/// other hashers. Panics if `height` is greater than 256.
+#[cfg(feature = "alloc")]
+pub fn default_hash(height: usize) -> &'static Hash256 {
+    &DEFAULT_HASHES[height]
+}
+
 /// Check the merkle proof of a key-value pair in a SMT-Map (specified by its merkle root). Returns
 /// whether the proof is valid.
+#[cfg(feature = "alloc")]
 pub fn check_merkle_proof(
     merkle_root: &Hash256,
     key: &Key,
     value: &Value,
     proof: &MerkleProof,
 ) -> bool {
+    check_merkle_proof_ref(merkle_root, key, value, proof.into())
+}
+
+/// Same as `check_merkle_proof`, but for an SMT-Map using the hasher `H`.
+#[cfg(feature = "alloc")]
+pub fn check_merkle_proof_with<H: Hasher>(
+    merkle_root: &Hash256,
+    key: &Key,
+    value: &Value,
+    proof: &MerkleProof,
+) -> bool {
+    check_merkle_proof_with_order::<H, LsbFirst>(merkle_root, key, value, proof)
+}
+
+/// Same as `check_merkle_proof`, but for an SMT-Map using the hasher `H` and the bit order `O`.
+#[cfg(feature = "alloc")]
+pub fn check_merkle_proof_with_order<H: Hasher, O: BitOrder>(
+    merkle_root: &Hash256,
+    key: &Key,
+    value: &Value,
+    proof: &MerkleProof,
+) -> bool {
+    let proof = MerkleProofRef {
+        bitmap: &O::reorder(&proof.bitmap),
+        hashes: &proof.hashes,
+    };
+    let default_hashes = H::default_hashes().iter().copied();
+    compute_root(&O::reorder(key), value, proof, default_hashes, H::merge)
+        .is_some_and(|root| root == *merkle_root)
+}
+
+/// Same as `check_merkle_proof`, but takes a borrowed proof and never allocates.
+pub fn check_merkle_proof_ref(
+    merkle_root: &Hash256,
+    key: &Key,
+    value: &Value,
+    proof: MerkleProofRef,
+) -> bool {
+    compute_merkle_root(key, value, proof).is_some_and(|root| root == *merkle_root)
+}
+
+/// Returns the merkle root of the SMT-Map in which `proof` proves the key-value pair, or `None` if
+/// the number of hashes does not match the bitmap.
+fn compute_merkle_root(key: &Key, value: &Value, proof: MerkleProofRef) -> Option<Hash256> {
+    compute_root(key, value, proof, default_hashes(), |left, right| {
+        Some(merge_hashes(left, right))
+    })
+}
+
+/// Same as `compute_merkle_root`, with the default hashes of height 0, 1, ..., 255 and the hash
+/// function given. `merge` returns `None` for hashes it does not accept.
+fn compute_root<D, M>(
+    key: &Key,
+    value: &Value,
+    proof: MerkleProofRef,
+    default_hashes: D,
+    merge: M,
+) -> Option<Hash256>
+where
+    D: Iterator<Item = Hash256>,
+    M: Fn(&Hash256, &Hash256) -> Option<Hash256>,
+{
     let mut hash = *value;
     let mut iter = proof.hashes.iter();
-    for i in 0..256 {
-        let sibling_hash = if !bit_op::get_bit(&proof.bitmap, i) {
-            &(*DEFAULT_HASHES)[i]
+    for (i, default_hash) in default_hashes.take(256).enumerate() {
+        let sibling_hash = if !bit_op::get_bit(proof.bitmap, i) {
+            default_hash
         } else {
-            if let Some(h) = iter.next() {
-                h
-            } else {
-                return false;
-            }
+            *iter.next()?
         };
 
         let depth = 256 - i;
         hash = if bit_op::get_bit(key, depth - 1) {
             // sibling is at left
-            merge_hashes(sibling_hash, &hash)
+            merge(&sibling_hash, &hash)?
         } else {
             // sibling is at right
-            merge_hashes(&hash, sibling_hash)
+            merge(&hash, &sibling_hash)?
         };
     }
 
-    iter.next() == None && hash == *merkle_root
+    if iter.next().is_some() {
+        return None;
+    }
+    Some(hash)
+}
+
+/// Returns the hashes of the default subtrees of height 0, 1, ..., 256.
+#[cfg(feature = "alloc")]
+fn default_hashes() -> impl Iterator<Item = Hash256> {
+    DEFAULT_HASHES.iter().copied()
+}
+
+/// Returns the hashes of the default subtrees of height 0, 1, ..., 256, computing them on the fly
+/// as there is no heap for `DEFAULT_HASHES`.
+#[cfg(not(feature = "alloc"))]
+fn default_hashes() -> impl Iterator<Item = Hash256> {
+    core::iter::successors(Some([0; 32]), |hash| Some(merge_hashes(hash, hash))).take(257)
 }
 
 fn merge_hashes(left: &Hash256, right: &Hash256) -> Hash256 {
     let mut hasher = tiny_keccak::Keccak::new_keccak256();
-    hasher.update(&*left);
-    hasher.update(&*right);
+    hasher.update(left);
+    hasher.update(right);
     let mut merged: Hash256 = [0; 32];
     hasher.finalize(&mut merged);
     merged
@@ -0,0 +1,215 @@
+//! Two-level maps, like the storage of Ethereum accounts: a top-level `SmtMap256` from accounts to
+//! the roots of per-account `SmtMap256`s from slots to values.
+//!
+//! The value of an account in the top-level map is the merkle root of its child map, except that
+//! an account whose child map is empty has the default value (zero) rather than the root of the
+//! empty map. Accounts without slots are thus the same as absent accounts.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::{
+    check_merkle_proof, compute_merkle_root, Hash256, Key, MerkleProof, SmtMap256, Value,
+    DEFAULT_HASHES,
+};
+
+/// Merkle proof of a certain tuple (root, account, slot, value) in a `NestedSmtMap`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NestedMerkleProof {
+    /// Proof of the account in the top-level map.
+    pub account_proof: MerkleProof,
+
+    /// Proof of the slot in the child map of the account.
+    pub slot_proof: MerkleProof,
+}
+
+/// Map from (account, slot) pairs to values, with a child `SmtMap256` per account.
+#[derive(Clone, Default)]
+pub struct NestedSmtMap {
+    top: SmtMap256,
+
+    // The child maps of the accounts with non-default values.
+    children: BTreeMap<Key, SmtMap256>,
+}
+
+impl NestedSmtMap {
+    /// Returns a new map where all slots of all accounts have the default value (zero).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of a slot of an account, and updates the value of the account in the
+    /// top-level map. Returns the old value of the slot.
+    pub fn set(&mut self, account: &Key, slot: &Key, value: Value) -> Value {
+        let child = self.children.entry(*account).or_default();
+        let old_value = child.set(slot, value);
+        let account_value = if child.is_empty() {
+            self.children.remove(account);
+            [0; 32]
+        } else {
+            *child.merkle_root()
+        };
+        self.top.set(account, account_value);
+        old_value
+    }
+
+    /// Returns a reference to the value of a slot of an account.
+    pub fn get(&self, account: &Key, slot: &Key) -> &Value {
+        match self.children.get(account) {
+            Some(child) => child.get(slot),
+            None => &[0; 32],
+        }
+    }
+
+    /// Returns the child map of an account, or `None` if all its slots have the default value.
+    pub fn child(&self, account: &Key) -> Option<&SmtMap256> {
+        self.children.get(account)
+    }
+
+    /// Returns a reference to the value of a slot of an account with merkle proof.
+    pub fn get_with_proof(&self, account: &Key, slot: &Key) -> (&Value, NestedMerkleProof) {
+        let (_, account_proof) = self.top.get_with_proof(account);
+        let (value, slot_proof) = match self.children.get(account) {
+            Some(child) => child.get_with_proof(slot),
+            None => (
+                &[0; 32],
+                MerkleProof {
+                    bitmap: [0; 32],
+                    hashes: Vec::new(),
+                },
+            ),
+        };
+        let proof = NestedMerkleProof {
+            account_proof,
+            slot_proof,
+        };
+        (value, proof)
+    }
+
+    /// Returns the merkle root of the top-level map.
+    pub fn merkle_root(&self) -> &Hash256 {
+        self.top.merkle_root()
+    }
+
+    /// Returns the number of accounts with non-default values.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Whether all slots of all accounts have the default value.
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Check the merkle proof of a slot value of an account in this map. Returns whether the
+    /// proof is valid.
+    pub fn check_merkle_proof(
+        &self,
+        account: &Key,
+        slot: &Key,
+        value: &Value,
+        proof: &NestedMerkleProof,
+    ) -> bool {
+        check_nested_merkle_proof(self.merkle_root(), account, slot, value, proof)
+    }
+}
+
+/// Check the merkle proof of a slot value of an account in a `NestedSmtMap` (specified by its
+/// merkle root). Returns whether the proof is valid.
+pub fn check_nested_merkle_proof(
+    merkle_root: &Hash256,
+    account: &Key,
+    slot: &Key,
+    value: &Value,
+    proof: &NestedMerkleProof,
+) -> bool {
+    let child_root = match compute_merkle_root(slot, value, (&proof.slot_proof).into()) {
+        Some(child_root) => child_root,
+        None => return false,
+    };
+    let account_value = if child_root == DEFAULT_HASHES[256] {
+        [0; 32]
+    } else {
+        child_root
+    };
+    check_merkle_proof(merkle_root, account, &account_value, &proof.account_proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_smt_map() {
+        let mut nested = NestedSmtMap::new();
+        let empty_root = *nested.merkle_root();
+        let (alice, bob) = ([1; 32], [2; 32]);
+        let (slot1, slot2) = ([0x10; 32], [0x20; 32]);
+
+        let (value, proof) = nested.get_with_proof(&alice, &slot1);
+        assert_eq!(*value, [0; 32]);
+        assert!(nested.check_merkle_proof(&alice, &slot1, value, &proof));
+
+        assert_eq!(nested.set(&alice, &slot1, [0xaa; 32]), [0; 32]);
+        nested.set(&alice, &slot2, [0xbb; 32]);
+        nested.set(&bob, &slot1, [0xcc; 32]);
+        assert_eq!(nested.set(&alice, &slot1, [0xdd; 32]), [0xaa; 32]);
+        assert_eq!(*nested.get(&alice, &slot1), [0xdd; 32]);
+        assert_eq!(*nested.get(&bob, &slot2), [0; 32]);
+        assert_eq!(nested.len(), 2);
+
+        // The top-level map holds the roots of the child maps.
+        let mut alice_map = SmtMap256::new();
+        alice_map.set(&slot1, [0xdd; 32]);
+        alice_map.set(&slot2, [0xbb; 32]);
+        assert_eq!(
+            nested.child(&alice).unwrap().merkle_root(),
+            alice_map.merkle_root()
+        );
+        let mut top = SmtMap256::new();
+        top.set(&alice, *alice_map.merkle_root());
+        top.set(&bob, *nested.child(&bob).unwrap().merkle_root());
+        assert_eq!(nested.merkle_root(), top.merkle_root());
+
+        let root = *nested.merkle_root();
+        for (account, slot) in [(alice, slot1), (alice, slot2), (bob, slot1), (bob, slot2)] {
+            let (value, proof) = nested.get_with_proof(&account, &slot);
+            assert!(check_nested_merkle_proof(
+                &root, &account, &slot, value, &proof
+            ));
+            assert!(!check_nested_merkle_proof(
+                &root,
+                &account,
+                &slot,
+                &[0xee; 32],
+                &proof
+            ));
+            assert!(!check_nested_merkle_proof(
+                &empty_root,
+                &account,
+                &slot,
+                value,
+                &proof
+            ));
+        }
+
+        // A proof of an absent account proves that its slots have the default value.
+        let carol = [3; 32];
+        let (value, proof) = nested.get_with_proof(&carol, &slot1);
+        assert!(nested.check_merkle_proof(&carol, &slot1, value, &proof));
+        assert!(!nested.check_merkle_proof(&carol, &slot1, &[0xaa; 32], &proof));
+
+        // A slot proof for the wrong account.
+        let (value, mut proof) = nested.get_with_proof(&bob, &slot1);
+        proof.account_proof = nested.get_with_proof(&alice, &slot1).1.account_proof;
+        assert!(!nested.check_merkle_proof(&alice, &slot1, value, &proof));
+
+        // Clearing all the slots of the accounts removes them.
+        nested.set(&bob, &slot1, [0; 32]);
+        assert!(nested.child(&bob).is_none());
+        nested.set(&alice, &slot1, [0; 32]);
+        nested.set(&alice, &slot2, [0; 32]);
+        assert!(nested.is_empty());
+        assert_eq!(*nested.merkle_root(), empty_root);
+    }
+}
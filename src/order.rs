@@ -0,0 +1,42 @@
+//! Orders of the bits of the keys of an `SmtMap256`, and of the bitmaps of its merkle proofs.
+//!
+//! The path from the root to the leaf of a key is the sequence of the bits of the key, the bit 1
+//! meaning right. `LsbFirst` (the default) takes the bits of each byte from the least significant
+//! one, as `NodeIndex` does internally. `MsbFirst` takes them from the most significant one,
+//! so the path is the binary representation of the key read as a big-endian integer, as in most
+//! other implementations and in EVM verifiers. In that order the leaves are sorted by key from
+//! left to right, so the keys of a map are kept in tree order.
+//!
+//! The bitmap of a `MerkleProof` follows the same order: its `i`-th bit tells whether the sibling
+//! of height `i` is non-default.
+
+/// Order of the bits of the keys and the proof bitmaps of an `SmtMap256`.
+pub trait BitOrder {
+    /// Converts a key or a bitmap between this order and `LsbFirst`. Converting twice must give
+    /// back the original bits.
+    fn reorder(bits: &[u8; 32]) -> [u8; 32];
+}
+
+/// The bits of each byte from the least significant one.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LsbFirst;
+
+impl BitOrder for LsbFirst {
+    fn reorder(bits: &[u8; 32]) -> [u8; 32] {
+        *bits
+    }
+}
+
+/// The bits of each byte from the most significant one.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MsbFirst;
+
+impl BitOrder for MsbFirst {
+    fn reorder(bits: &[u8; 32]) -> [u8; 32] {
+        let mut result = *bits;
+        for byte in result.iter_mut() {
+            *byte = byte.reverse_bits();
+        }
+        result
+    }
+}
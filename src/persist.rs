@@ -0,0 +1,415 @@
+//! A disk-backed `SmtMap256` whose merkle root survives crashes.
+//!
+//! The map lives in a directory with two files:
+//!
+//! * `nodes`: an append-only log of node records, each being the depth (2 bytes, little-endian),
+//!   the bit path (32 bytes) and the hash (32 bytes) of a node. The last record of a node wins,
+//!   and a record with the default hash removes the node. Leaves are the nodes of depth 256.
+//! * `wal`: the write-ahead log. Each record is the length of its payload (4 bytes,
+//!   little-endian), the payload, and the keccak-256 of the payload. The payload is the number of
+//!   updates (4 bytes, little-endian) followed by the updates as (key, value).
+//!
+//! An update is first appended to the WAL and synced, then its node records are appended to
+//! `nodes` and synced, and finally the WAL is cleared. When the map is opened, a complete WAL
+//! record is replayed, since its node records may have been written only partially. A torn WAL
+//! record is discarded: no node record of it has been written yet, so this rolls the update back.
+
+use alloc::collections::btree_set::BTreeSet;
+use alloc::vec::Vec;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{Hash256, Key, MerkleProof, NodeIndex, SmtMap256, Value};
+
+const NODES_FILE: &str = "nodes";
+const NODES_TMP_FILE: &str = "nodes.tmp";
+const WAL_FILE: &str = "wal";
+
+const NODE_RECORD_LEN: usize = 2 + 32 + 32;
+
+/// An `SmtMap256` persisted in a directory, with crash recovery.
+///
+/// If a method returns an I/O error, the in-memory state may be ahead of the files. Drop the map
+/// and open it again to recover.
+pub struct PersistentSmtMap {
+    dir: PathBuf,
+    map: SmtMap256,
+    nodes: File,
+    wal: File,
+}
+
+impl PersistentSmtMap {
+    /// Opens the map stored in `dir`, creating an empty one if `dir` holds no map, and recovers
+    /// from an interrupted update.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut nodes = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(NODES_FILE))?;
+        let mut wal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(WAL_FILE))?;
+
+        let mut bytes = Vec::new();
+        nodes.read_to_end(&mut bytes)?;
+        let (map, complete_len) = load_nodes(&bytes)?;
+        if complete_len != bytes.len() {
+            // Drop a torn record, so that the following records are aligned.
+            nodes.set_len(complete_len as u64)?;
+        }
+
+        let mut bytes = Vec::new();
+        wal.read_to_end(&mut bytes)?;
+        let batches = decode_wal(&bytes);
+
+        let mut persistent = Self {
+            dir,
+            map,
+            nodes,
+            wal,
+        };
+        for updates in batches {
+            persistent.apply(&updates)?;
+        }
+        persistent.clear_wal()?;
+        Ok(persistent)
+    }
+
+    /// Returns a reference to the value of a key.
+    pub fn get(&self, key: &Key) -> &Value {
+        self.map.get(key)
+    }
+
+    /// Returns a reference to the value of the key with merkle proof.
+    pub fn get_with_proof(&self, key: &Key) -> (&Value, MerkleProof) {
+        self.map.get_with_proof(key)
+    }
+
+    /// Returns the merkle root of the map.
+    pub fn merkle_root(&self) -> &Hash256 {
+        self.map.merkle_root()
+    }
+
+    /// Returns the in-memory map.
+    pub fn map(&self) -> &SmtMap256 {
+        &self.map
+    }
+
+    /// Sets the value of a key durably. Returns the old value of the key.
+    pub fn set(&mut self, key: &Key, value: Value) -> io::Result<Value> {
+        let old_value = *self.map.get(key);
+        self.set_batch(&[(*key, value)])?;
+        Ok(old_value)
+    }
+
+    /// Sets the values of several keys atomically and durably. Later updates of the same key win.
+    pub fn set_batch(&mut self, updates: &[(Key, Value)]) -> io::Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+        self.wal.write_all(&encode_wal_record(updates))?;
+        self.wal.sync_data()?;
+        self.apply(updates)?;
+        self.clear_wal()
+    }
+
+    /// Rewrites the node log with only the current nodes.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let tmp_path = self.dir.join(NODES_TMP_FILE);
+        let mut tmp = File::create(&tmp_path)?;
+        let mut bytes = Vec::with_capacity(self.map.hashes.len() * NODE_RECORD_LEN);
+        for (index, hash) in &self.map.hashes {
+            encode_node_record(&mut bytes, index, hash);
+        }
+        tmp.write_all(&bytes)?;
+        tmp.sync_all()?;
+        drop(tmp);
+
+        fs::rename(&tmp_path, self.dir.join(NODES_FILE))?;
+        if let Ok(dir) = File::open(&self.dir) {
+            // Persist the rename where directories can be synced.
+            let _ = dir.sync_all();
+        }
+        self.nodes = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(self.dir.join(NODES_FILE))?;
+        Ok(())
+    }
+
+    /// Size in bytes of the node log.
+    pub fn node_log_len(&self) -> io::Result<u64> {
+        Ok(self.nodes.metadata()?.len())
+    }
+
+    // Applies updates to the map, and appends the new hashes of the touched nodes to the log.
+    fn apply(&mut self, updates: &[(Key, Value)]) -> io::Result<()> {
+        let mut touched = BTreeSet::new();
+        for (key, value) in updates {
+            self.map.set(key, *value);
+            if *value == [0; 32] {
+                self.map.kvs.remove(key);
+            }
+            let mut index = NodeIndex::leaf(*key);
+            touched.insert(index.clone());
+            while !index.is_root() {
+                index.move_up();
+                touched.insert(index.clone());
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(touched.len() * NODE_RECORD_LEN);
+        for index in &touched {
+            encode_node_record(&mut bytes, index, self.map.get_hash(index));
+        }
+        self.nodes.write_all(&bytes)?;
+        self.nodes.sync_data()
+    }
+
+    fn clear_wal(&mut self) -> io::Result<()> {
+        self.wal.set_len(0)?;
+        self.wal.seek(SeekFrom::Start(0))?;
+        self.wal.sync_data()
+    }
+}
+
+// Rebuilds a map from the records of the node log, ignoring a torn record at the end. Returns the
+// map and the length of the complete records.
+fn load_nodes(bytes: &[u8]) -> io::Result<(SmtMap256, usize)> {
+    let complete_len = bytes.len() - bytes.len() % NODE_RECORD_LEN;
+    let mut map = SmtMap256::new();
+    for record in bytes[..complete_len].chunks(NODE_RECORD_LEN) {
+        let (index, hash) = decode_node_record(record)?;
+        if index.depth == 256 {
+            if hash == [0; 32] {
+                map.kvs.remove(&index.bit_path);
+            } else {
+                map.kvs.insert(index.bit_path, hash);
+            }
+        }
+        map.update_hash(&index, &hash);
+    }
+    Ok((map, complete_len))
+}
+
+fn encode_node_record(bytes: &mut Vec<u8>, index: &NodeIndex, hash: &Hash256) {
+    bytes.extend_from_slice(&(index.depth as u16).to_le_bytes());
+    bytes.extend_from_slice(&index.bit_path);
+    bytes.extend_from_slice(hash);
+}
+
+fn decode_node_record(record: &[u8]) -> io::Result<(NodeIndex, Hash256)> {
+    let depth = u16::from_le_bytes([record[0], record[1]]) as usize;
+    let mut bit_path = [0; 32];
+    bit_path.copy_from_slice(&record[2..34]);
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&record[34..]);
+    if depth > 256 || !is_zero_from(&bit_path, depth) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid node index in the node log",
+        ));
+    }
+    Ok((NodeIndex { bit_path, depth }, hash))
+}
+
+// Whether the bits of `bit_path` from bit `i` on are all zero.
+fn is_zero_from(bit_path: &[u8; 32], i: usize) -> bool {
+    let (byte, bit) = (i / 8, i % 8);
+    byte == 32 || (bit_path[byte] >> bit == 0 && bit_path[byte + 1..].iter().all(|b| *b == 0))
+}
+
+fn encode_wal_record(updates: &[(Key, Value)]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(4 + 64 * updates.len());
+    payload.extend_from_slice(&(updates.len() as u32).to_le_bytes());
+    for (key, value) in updates {
+        payload.extend_from_slice(key);
+        payload.extend_from_slice(value);
+    }
+
+    let mut record = Vec::with_capacity(4 + payload.len() + 32);
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record.extend_from_slice(&checksum(&payload));
+    record
+}
+
+// Returns the updates of the complete records, stopping at the first torn or corrupted one.
+fn decode_wal(mut bytes: &[u8]) -> Vec<Vec<(Key, Value)>> {
+    let mut batches = Vec::new();
+    while bytes.len() >= 4 {
+        let len = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let record_len = match len.checked_add(4 + 32) {
+            Some(record_len) if len >= 4 && record_len <= bytes.len() => record_len,
+            _ => break,
+        };
+        let payload = &bytes[4..4 + len];
+        if checksum(payload)[..] != bytes[4 + len..4 + len + 32] {
+            break;
+        }
+        let count = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+        if (len - 4) / 64 != count || !(len - 4).is_multiple_of(64) {
+            break;
+        }
+        let updates = payload[4..]
+            .chunks(64)
+            .map(|update| {
+                let (mut key, mut value) = ([0; 32], [0; 32]);
+                key.copy_from_slice(&update[..32]);
+                value.copy_from_slice(&update[32..]);
+                (key, value)
+            })
+            .collect();
+        batches.push(updates);
+        bytes = &bytes[record_len..];
+    }
+    batches
+}
+
+fn checksum(payload: &[u8]) -> Hash256 {
+    let mut hasher = tiny_keccak::Keccak::new_keccak256();
+    hasher.update(payload);
+    let mut hash = [0; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("smt_map_persist_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn key(i: u8) -> Key {
+        let mut key = [i.wrapping_mul(77); 32];
+        key[0] = i;
+        key
+    }
+
+    // Returns whether every stored inner hash is the merge of the hashes of its children, and
+    // every leaf hash is the value of its key.
+    fn is_consistent(map: &SmtMap256) -> bool {
+        map.hashes.iter().all(|(index, hash)| {
+            if index.depth == 256 {
+                map.get(&index.bit_path) == hash
+            } else {
+                crate::merge_hashes(
+                    map.get_hash(&index.left_child()),
+                    map.get_hash(&index.right_child()),
+                ) == *hash
+            }
+        }) && map
+            .kvs
+            .iter()
+            .all(|(key, value)| map.get_hash(&NodeIndex::leaf(*key)) == value)
+    }
+
+    #[test]
+    fn test_persistent_map() {
+        let dir = temp_dir("basic");
+        let mut smt = SmtMap256::new();
+        {
+            let mut map = PersistentSmtMap::open(&dir).unwrap();
+            assert_eq!(map.merkle_root(), smt.merkle_root());
+            for i in 0..10 {
+                assert_eq!(map.set(&key(i), [i + 1; 32]).unwrap(), [0; 32]);
+                smt.set(&key(i), [i + 1; 32]);
+            }
+            assert_eq!(map.set(&key(3), [0; 32]).unwrap(), [4; 32]);
+            smt.set(&key(3), [0; 32]);
+            map.set_batch(&[(key(4), [9; 32]), (key(20), [8; 32]), (key(4), [7; 32])])
+                .unwrap();
+            smt.set(&key(4), [7; 32]);
+            smt.set(&key(20), [8; 32]);
+            assert_eq!(map.merkle_root(), smt.merkle_root());
+        }
+
+        let mut map = PersistentSmtMap::open(&dir).unwrap();
+        assert_eq!(map.merkle_root(), smt.merkle_root());
+        assert_eq!(*map.get(&key(4)), [7; 32]);
+        assert_eq!(*map.get(&key(3)), [0; 32]);
+        assert!(is_consistent(map.map()));
+
+        let len = map.node_log_len().unwrap();
+        map.compact().unwrap();
+        assert!(map.node_log_len().unwrap() < len);
+        map.set(&key(5), [5; 32]).unwrap();
+        smt.set(&key(5), [5; 32]);
+        drop(map);
+
+        let map = PersistentSmtMap::open(&dir).unwrap();
+        assert_eq!(map.merkle_root(), smt.merkle_root());
+        assert_eq!(map.get_with_proof(&key(5)).1, smt.get_with_proof(&key(5)).1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_crash_recovery() {
+        let dir = temp_dir("crash");
+        let mut map = PersistentSmtMap::open(&dir).unwrap();
+        for i in 1..3 {
+            map.set(&key(i), [i; 32]).unwrap();
+        }
+        let root_before = *map.merkle_root();
+        let nodes_before = fs::read(dir.join(NODES_FILE)).unwrap();
+
+        // A single update appends one path of node records, short enough to be torn at every
+        // offset.
+        let updates = [(key(1), [0; 32])];
+        map.set_batch(&updates).unwrap();
+        let root_after = *map.merkle_root();
+        let nodes_after = fs::read(dir.join(NODES_FILE)).unwrap();
+        drop(map);
+        let record = encode_wal_record(&updates);
+
+        let crash_and_recover = |wal: &[u8], nodes: &[u8]| {
+            fs::write(dir.join(WAL_FILE), wal).unwrap();
+            fs::write(dir.join(NODES_FILE), nodes).unwrap();
+            let map = PersistentSmtMap::open(&dir).unwrap();
+            assert!(is_consistent(map.map()));
+            let root = *map.merkle_root();
+            drop(map);
+
+            // Recovery leaves the files in a clean state.
+            assert_eq!(fs::read(dir.join(WAL_FILE)).unwrap().len(), 0);
+            assert_eq!(*PersistentSmtMap::open(&dir).unwrap().merkle_root(), root);
+            root
+        };
+
+        // Crash while appending to the WAL: the update is rolled back.
+        for len in 0..record.len() {
+            assert_eq!(
+                crash_and_recover(&record[..len], &nodes_before),
+                root_before
+            );
+        }
+
+        // Crash while appending node records, at every offset: the torn record is dropped and the
+        // update is replayed.
+        let appended = &nodes_after[nodes_before.len()..];
+        for len in 0..=appended.len() {
+            let nodes = &nodes_after[..nodes_before.len() + len];
+            assert_eq!(crash_and_recover(&record, nodes), root_after);
+
+            let (_, complete_len) = load_nodes(&appended[..len]).unwrap();
+            assert_eq!(complete_len, len - len % NODE_RECORD_LEN);
+        }
+
+        // Crash before clearing the WAL.
+        assert_eq!(crash_and_recover(&record, &nodes_after), root_after);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! Poseidon hasher over the BN254 scalar field, for maps whose proofs are verified in zkSNARK
+//! circuits.
+//!
+//! Keys, values and hashes are field elements in 32-byte big-endian, so only words below the
+//! field modulus are accepted. The hash of an inner node is `Poseidon(left, right)` with the
+//! parameters of circomlib (`x^5` S-box, 8 full and 57 partial rounds for two inputs), and the
+//! default hashes are `Poseidon(d, d)` of the default hash `d` one level below, starting from
+//! zero. A circuit verifying a `MerkleProof` thus computes, for each of the 256 levels from the
+//! leaf up, `Poseidon(hash, sibling)` or `Poseidon(sibling, hash)` depending on the key bit, with
+//! the sibling being either the next proof hash or the default hash of the level.
+
+use core::cell::RefCell;
+
+use ark_bn254::Fr;
+use light_poseidon::{Poseidon as PoseidonState, PoseidonBytesHasher};
+
+use crate::hasher::Hasher;
+use crate::Hash256;
+
+/// Big-endian modulus of the BN254 scalar field.
+pub const MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x28, 0x33, 0xe8, 0x48, 0x79, 0xb9, 0x70, 0x91, 0x43, 0xe1, 0xf5, 0x93, 0xf0, 0x00, 0x00, 0x01,
+];
+
+std::thread_local! {
+    static STATE: RefCell<PoseidonState<Fr>> =
+        RefCell::new(PoseidonState::<Fr>::new_circom(2).unwrap());
+}
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_HASHES: [Hash256; 257] = {
+        let mut hashes = [[0; 32]; 257];
+        for i in 1..=256 {
+            hashes[i] = Poseidon::merge(&hashes[i - 1], &hashes[i - 1]).unwrap();
+        }
+        hashes
+    };
+}
+
+/// Poseidon over the BN254 scalar field with the parameters of circomlib.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Poseidon;
+
+impl Hasher for Poseidon {
+    fn merge(left: &Hash256, right: &Hash256) -> Option<Hash256> {
+        if !Self::accepts(left) || !Self::accepts(right) {
+            return None;
+        }
+        STATE.with(|state| state.borrow_mut().hash_bytes_be(&[left, right]).ok())
+    }
+
+    fn accepts(word: &[u8; 32]) -> bool {
+        *word < MODULUS
+    }
+
+    fn default_hashes() -> &'static [Hash256; 257] {
+        &DEFAULT_HASHES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::NotAccepted;
+    use crate::store::BTreeStorage;
+    use crate::{check_merkle_proof_with, SmtMap256};
+
+    fn b256(hex: &str) -> Hash256 {
+        let mut word = [0; 32];
+        word.copy_from_slice(&hex::decode(hex).unwrap());
+        word
+    }
+
+    fn r256(n: u8) -> Hash256 {
+        let mut word = [0; 32];
+        word[31] = n;
+        word
+    }
+
+    #[test]
+    fn test_poseidon_vectors() {
+        // circomlibjs: poseidon([1, 2]).
+        assert_eq!(
+            Poseidon::merge(&r256(1), &r256(2)),
+            Some(b256(
+                "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"
+            ))
+        );
+        // The default hashes of height 1 and 2, the zero values of the Poseidon merkle trees of
+        // Semaphore and Tornado Cash Nova.
+        let default_hashes = Poseidon::default_hashes();
+        assert_eq!(default_hashes[0], [0; 32]);
+        assert_eq!(
+            default_hashes[1],
+            b256("2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864")
+        );
+        assert_eq!(
+            default_hashes[2],
+            b256("1069673dcdb12263df301a6ff584a7ec261a44cb9dc68df067a4774460b1f1e1")
+        );
+
+        let mut too_large = MODULUS;
+        assert_eq!(Poseidon::merge(&too_large, &r256(1)), None);
+        too_large[31] -= 1;
+        assert!(Poseidon::merge(&too_large, &r256(1)).is_some());
+    }
+
+    #[test]
+    fn test_poseidon_map() {
+        let mut smt = SmtMap256::<BTreeStorage, Poseidon>::default();
+        assert_eq!(*smt.merkle_root(), Poseidon::default_hashes()[256]);
+
+        let key = r256(0xc0);
+        smt.set(&r256(0), r256(0xaa));
+        smt.set(
+            &b256("1234000000000000000000000000000000000000000000000000000000000000"),
+            r256(7),
+        );
+        let (value, proof) = smt.get_with_proof(&key);
+        assert_eq!(proof.hashes.len(), 2);
+        assert!(smt.check_merkle_proof(&key, value, &proof));
+        assert!(check_merkle_proof_with::<Poseidon>(
+            smt.merkle_root(),
+            &key,
+            value,
+            &proof
+        ));
+        assert!(!crate::check_merkle_proof(
+            smt.merkle_root(),
+            &key,
+            value,
+            &proof
+        ));
+
+        // Proofs with hashes outside the field are rejected, not reduced.
+        let mut invalid = proof.clone();
+        invalid.hashes[0] = [0xff; 32];
+        assert!(!smt.check_merkle_proof(&key, value, &invalid));
+        assert!(!smt.check_merkle_proof(&key, &MODULUS, &proof));
+
+        // Words outside the field are rejected without touching the map.
+        let root = *smt.merkle_root();
+        assert_eq!(smt.try_set(&MODULUS, [0; 32]), Err(NotAccepted(MODULUS)));
+        assert_eq!(smt.try_set(&r256(2), MODULUS), Err(NotAccepted(MODULUS)));
+        assert_eq!(*smt.merkle_root(), root);
+        assert_eq!(*smt.get(&r256(2)), [0; 32]);
+
+        // Resetting the values restores the empty root.
+        smt.set(&r256(0), [0; 32]);
+        smt.set(
+            &b256("1234000000000000000000000000000000000000000000000000000000000000"),
+            [0; 32],
+        );
+        assert_eq!(*smt.merkle_root(), Poseidon::default_hashes()[256]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not accepted by the hasher")]
+    fn test_poseidon_map_rejects_large_values() {
+        SmtMap256::<BTreeStorage, Poseidon>::default().set(&r256(1), [0xff; 32]);
+    }
+}
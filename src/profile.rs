@@ -0,0 +1,500 @@
+//! Profiles fixing the layout of a sparse merkle tree to match the specification of a third-party
+//! verifier.
+//!
+//! A profile fixes the path of a key in the tree, the hashes of the leaves and of the inner nodes,
+//! the hash of an empty subtree, and whether the tree is compacted. A compacted tree replaces each
+//! subtree holding a single leaf by the leaf, so its leaves hash their keys along with their
+//! values. Paths are read from the most significant bit of each byte, the bit 1 meaning right. As
+//! in `SmtMap256`, a key is unset when its value is zero.
+//!
+//! - `Native` is the layout of `SmtMap256`: full depth, keccak-256 inner nodes, leaves hashing to
+//!   their values, and the bits of the keys in the `LsbFirst` order.
+//! - `Jellyfish` is the Jellyfish Merkle Tree of Aptos: compacted, SHA3-256 salted with the hash
+//!   of `APTOS::SparseMerkleLeafNode` for the leaves (the key then the value) and of
+//!   `APTOS::SparseMerkleInternal` for the inner nodes, and `SPARSE_MERKLE_PLACEHOLDER_HASH` for
+//!   the empty subtrees. Keys and values stand for the hashes of the state keys and values.
+//!
+//! The maps of all profiles are `ProfileMap`s, and their proofs `ProfileProof`s, laid out as the
+//! `SparseMerkleProof`s of Jellyfish. The maps keep no inner nodes, so they suit producing roots
+//! and proofs for other verifiers rather than large states; for the `Native` layout, `SmtMap256`
+//! gives the same roots.
+//!
+//! The tests check `Native` against `SmtMap256`, and `Jellyfish` against the node structures of
+//! the tests of the `aptos-jellyfish-merkle` crate. Those are written in terms of the hashes of
+//! the leaves and of the inner nodes, so the salted hashes themselves are not checked against
+//! hashes computed by Aptos.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use crate::hasher::{Hasher, Keccak256};
+use crate::order::{BitOrder, MsbFirst};
+use crate::{merge_hashes, Hash256, Key, Value};
+
+lazy_static::lazy_static! {
+    // The salts prefixed to the hashed data of the leaves and of the inner nodes of `Jellyfish`.
+    static ref JELLYFISH_LEAF_SALT: Hash256 = sha3_256(&[b"APTOS::SparseMerkleLeafNode"]);
+    static ref JELLYFISH_INNER_SALT: Hash256 = sha3_256(&[b"APTOS::SparseMerkleInternal"]);
+}
+
+/// The hash of the empty subtrees of `Jellyfish`: the name of the constant, padded with zeros.
+pub const JELLYFISH_PLACEHOLDER: Hash256 = *b"SPARSE_MERKLE_PLACEHOLDER_HASH\0\0";
+
+/// Layout of a sparse merkle tree matching a specification.
+pub trait Profile {
+    /// Name of the specification.
+    const NAME: &'static str;
+
+    /// Whether each subtree holding a single leaf is replaced by the leaf.
+    const COMPACTED: bool;
+
+    /// Returns the path from the root to the leaf of a key.
+    fn path(key: &Key) -> Hash256;
+
+    /// Returns the hash of the leaf of a key with a non-zero value.
+    fn hash_leaf(key: &Key, value: &Value) -> Hash256;
+
+    /// Returns the hash of an inner node with the given child hashes.
+    fn hash_inner(left: &Hash256, right: &Hash256) -> Hash256;
+
+    /// Returns the hash of an empty subtree of the given height.
+    fn empty_hash(height: usize) -> Hash256;
+}
+
+/// The layout of `SmtMap256`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Native;
+
+impl Profile for Native {
+    const NAME: &'static str = "smt_map";
+    const COMPACTED: bool = false;
+
+    fn path(key: &Key) -> Hash256 {
+        // Reversing the bits of each byte turns the `LsbFirst` order into the order of the paths.
+        MsbFirst::reorder(key)
+    }
+
+    fn hash_leaf(_key: &Key, value: &Value) -> Hash256 {
+        *value
+    }
+
+    fn hash_inner(left: &Hash256, right: &Hash256) -> Hash256 {
+        merge_hashes(left, right)
+    }
+
+    fn empty_hash(height: usize) -> Hash256 {
+        Keccak256::default_hashes()[height]
+    }
+}
+
+/// The Jellyfish Merkle Tree of Aptos.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Jellyfish;
+
+impl Profile for Jellyfish {
+    const NAME: &'static str = "aptos jellyfish merkle tree";
+    const COMPACTED: bool = true;
+
+    fn path(key: &Key) -> Hash256 {
+        *key
+    }
+
+    fn hash_leaf(key: &Key, value: &Value) -> Hash256 {
+        sha3_256(&[&*JELLYFISH_LEAF_SALT, key, value])
+    }
+
+    fn hash_inner(left: &Hash256, right: &Hash256) -> Hash256 {
+        sha3_256(&[&*JELLYFISH_INNER_SALT, left, right])
+    }
+
+    fn empty_hash(_height: usize) -> Hash256 {
+        JELLYFISH_PLACEHOLDER
+    }
+}
+
+/// Merkle proof of a key-value pair in a map of a profile.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProfileProof {
+    /// The key-value pair of the leaf at the end of the path: the proved pair if the value is
+    /// non-zero. Otherwise, in a compacted tree, the pair whose leaf takes the place of the key,
+    /// or `None` if the path ends in an empty subtree.
+    pub leaf: Option<(Key, Value)>,
+
+    /// The siblings along the path, from the leaf up. Always 256 hashes long in full-depth trees.
+    pub siblings: Vec<Hash256>,
+}
+
+/// Map from 256-bit keys to 256-bit values laid out as a tree of the profile `P`. Initially every
+/// key has a default value of zero.
+#[derive(Clone, Debug)]
+pub struct ProfileMap<P: Profile> {
+    // The key-value pairs with a non-zero value, by path. The paths are sorted in tree order.
+    leaves: BTreeMap<Hash256, (Key, Value)>,
+
+    profile: PhantomData<P>,
+}
+
+// A leaf of a `ProfileMap`: its path and its key-value pair.
+type Leaf<'a> = (&'a Hash256, &'a (Key, Value));
+
+impl<P: Profile> Default for ProfileMap<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Profile> ProfileMap<P> {
+    /// Returns a new map where all keys have the default value (zero).
+    pub fn new() -> Self {
+        Self {
+            leaves: BTreeMap::new(),
+            profile: PhantomData,
+        }
+    }
+
+    /// Sets the value of a key. Returns the old value of the key.
+    pub fn set(&mut self, key: &Key, value: Value) -> Value {
+        let old_leaf = if value == [0; 32] {
+            self.leaves.remove(&P::path(key))
+        } else {
+            self.leaves.insert(P::path(key), (*key, value))
+        };
+        old_leaf.map_or([0; 32], |(_, value)| value)
+    }
+
+    /// Returns a reference to the value of a key.
+    pub fn get(&self, key: &Key) -> &Value {
+        self.leaves
+            .get(&P::path(key))
+            .map_or(&[0; 32], |(_, value)| value)
+    }
+
+    /// Returns the number of keys with a non-zero value.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Whether all keys have the value zero.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Returns the merkle root of the map. Hashes the whole tree.
+    pub fn merkle_root(&self) -> Hash256 {
+        subtree_hash::<P>(&self.leaves.iter().collect::<Vec<_>>(), 0)
+    }
+
+    /// Returns a reference to the value of the key with merkle proof. Hashes the whole tree.
+    pub fn get_with_proof(&self, key: &Key) -> (&Value, ProfileProof) {
+        let path = P::path(key);
+        let all_leaves: Vec<Leaf> = self.leaves.iter().collect();
+        let mut leaves = &all_leaves[..];
+        let mut siblings = Vec::new();
+        let mut depth = 0;
+        while depth < 256 && !(P::COMPACTED && leaves.len() <= 1) {
+            let (left, right) = split(leaves, depth);
+            let (sibling, next) = if path_bit(&path, depth) {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            siblings.push(subtree_hash::<P>(sibling, depth + 1));
+            leaves = next;
+            depth += 1;
+        }
+        siblings.reverse();
+        let leaf = leaves.first().map(|(_, leaf)| **leaf);
+        (self.get(key), ProfileProof { leaf, siblings })
+    }
+
+    /// Check the merkle proof of a key-value pair in this map. Returns whether the proof is valid.
+    pub fn check_merkle_proof(&self, key: &Key, value: &Value, proof: &ProfileProof) -> bool {
+        check_merkle_proof::<P>(&self.merkle_root(), key, value, proof)
+    }
+}
+
+/// Check the merkle proof of a key-value pair in a map of the profile `P` (specified by its merkle
+/// root). Returns whether the proof is valid.
+pub fn check_merkle_proof<P: Profile>(
+    merkle_root: &Hash256,
+    key: &Key,
+    value: &Value,
+    proof: &ProfileProof,
+) -> bool {
+    let height = match 256_usize.checked_sub(proof.siblings.len()) {
+        Some(height) if P::COMPACTED || height == 0 => height,
+        _ => return false,
+    };
+    let path = P::path(key);
+    let mut hash = match &proof.leaf {
+        None if *value == [0; 32] => P::empty_hash(height),
+        None => return false,
+        Some((leaf_key, leaf_value)) => {
+            let is_valid = if *value == [0; 32] {
+                // Another leaf alone in the subtree which the path of the key ends in.
+                let leaf_path = P::path(leaf_key);
+                P::COMPACTED
+                    && leaf_path != path
+                    && (0..256 - height).all(|i| path_bit(&leaf_path, i) == path_bit(&path, i))
+            } else {
+                leaf_key == key && leaf_value == value
+            };
+            if !is_valid || *leaf_value == [0; 32] {
+                return false;
+            }
+            P::hash_leaf(leaf_key, leaf_value)
+        }
+    };
+    for (i, sibling) in proof.siblings.iter().enumerate() {
+        hash = if path_bit(&path, 255 - height - i) {
+            P::hash_inner(sibling, &hash)
+        } else {
+            P::hash_inner(&hash, sibling)
+        };
+    }
+    hash == *merkle_root
+}
+
+// Returns the hash of the subtree at `depth` holding `leaves`, which are in tree order.
+fn subtree_hash<P: Profile>(leaves: &[Leaf], depth: usize) -> Hash256 {
+    match leaves {
+        [] => P::empty_hash(256 - depth),
+        [(_, (key, value))] if P::COMPACTED || depth == 256 => P::hash_leaf(key, value),
+        _ => {
+            let (left, right) = split(leaves, depth);
+            P::hash_inner(
+                &subtree_hash::<P>(left, depth + 1),
+                &subtree_hash::<P>(right, depth + 1),
+            )
+        }
+    }
+}
+
+// Splits the leaves of the subtree at `depth` into the leaves of its left and right subtrees.
+fn split<'a, 'b>(leaves: &'b [Leaf<'a>], depth: usize) -> (&'b [Leaf<'a>], &'b [Leaf<'a>]) {
+    leaves.split_at(leaves.partition_point(|(path, _)| !path_bit(path, depth)))
+}
+
+// Whether the node at depth `i + 1` on the path is a right child.
+fn path_bit(path: &Hash256, i: usize) -> bool {
+    path[i / 8] & (0x80 >> (i % 8)) != 0
+}
+
+fn sha3_256(parts: &[&[u8]]) -> Hash256 {
+    let mut hasher = tiny_keccak::Keccak::new_sha3_256();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut hash = [0; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::FullMerkleProof;
+    use crate::SmtMap256;
+
+    #[test]
+    fn test_native_profile() {
+        // The map of `Native` and `SmtMap256` have the same roots and siblings.
+        let mut map = ProfileMap::<Native>::new();
+        let mut smt = SmtMap256::new();
+        for (key, value) in [([0x11; 32], [0xaa; 32]), ([0x22; 32], [0xbb; 32])] {
+            map.set(&key, value);
+            smt.set(&key, value);
+        }
+        assert_eq!(map.merkle_root(), *smt.merkle_root());
+        for key in [[0x22; 32], [0x10; 32]] {
+            let (value, proof) = map.get_with_proof(&key);
+            let (_, smt_proof) = smt.get_with_proof(&key);
+            let full = FullMerkleProof::from_proof::<Keccak256>(&smt_proof).unwrap();
+            assert_eq!(proof.siblings, full.siblings);
+            assert_eq!(proof.leaf.is_some(), *value != [0; 32]);
+            assert!(map.check_merkle_proof(&key, value, &proof));
+        }
+    }
+
+    // The Jellyfish tests below port `two_leaves_test1`, `two_leaves_test2` and
+    // `three_leaves_test1` of `src/node_type/node_type_test.rs` in `aptos-jellyfish-merkle` 0.1.0.
+    // Each puts leaves at some nibbles of an internal node, and gives the hash of the node, and
+    // the leaf and the siblings from the top of the node of the path of each nibble. Here the keys
+    // differ only in their last nibble, so the node is at depth 252 under empty siblings.
+
+    // Returns the key of the last nibble `nibble`, the other ones being zero.
+    fn nibble_key(nibble: u8) -> Key {
+        let mut key = [0; 32];
+        key[31] = nibble;
+        key
+    }
+
+    // Returns a map with a leaf at each of the nibbles, and the hashes of the leaves.
+    fn nibble_map(nibbles: &[u8]) -> (ProfileMap<Jellyfish>, Vec<Hash256>) {
+        let mut map = ProfileMap::new();
+        let hashes = nibbles
+            .iter()
+            .map(|nibble| {
+                let (key, value) = (nibble_key(*nibble), [nibble + 1; 32]);
+                map.set(&key, value);
+                Jellyfish::hash_leaf(&key, &value)
+            })
+            .collect();
+        (map, hashes)
+    }
+
+    fn check_node_hash(map: &ProfileMap<Jellyfish>, node_hash: Hash256) {
+        let root = (0..252).fold(node_hash, |hash, _| {
+            Jellyfish::hash_inner(&hash, &JELLYFISH_PLACEHOLDER)
+        });
+        assert_eq!(map.merkle_root(), root);
+    }
+
+    // Checks the proof of the key of `nibble` against the nibble of its leaf and its siblings in
+    // the node, from the top.
+    fn check_child_with_siblings(
+        map: &ProfileMap<Jellyfish>,
+        nibble: u8,
+        leaf_nibble: Option<u8>,
+        node_siblings: &[Hash256],
+    ) {
+        let key = nibble_key(nibble);
+        let (value, proof) = map.get_with_proof(&key);
+        let leaf = leaf_nibble.map(|leaf_nibble| {
+            let leaf_key = nibble_key(leaf_nibble);
+            (leaf_key, *map.get(&leaf_key))
+        });
+        assert_eq!(proof.leaf, leaf);
+        let mut siblings: Vec<Hash256> = node_siblings.iter().rev().copied().collect();
+        siblings.resize(node_siblings.len() + 252, JELLYFISH_PLACEHOLDER);
+        assert_eq!(proof.siblings, siblings);
+        assert!(map.check_merkle_proof(&key, value, &proof));
+        assert!(!map.check_merkle_proof(&key, &[0xdd; 32], &proof));
+    }
+
+    #[test]
+    fn test_jellyfish_two_leaves() {
+        for nibble1 in 0..8 {
+            for nibble2 in 8..16 {
+                let (map, hashes) = nibble_map(&[nibble1, nibble2]);
+                let (hash1, hash2) = (hashes[0], hashes[1]);
+                check_node_hash(&map, Jellyfish::hash_inner(&hash1, &hash2));
+                for i in 0..8 {
+                    check_child_with_siblings(&map, i, Some(nibble1), &[hash2]);
+                }
+                for i in 8..16 {
+                    check_child_with_siblings(&map, i, Some(nibble2), &[hash1]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_jellyfish_two_leaves_under_empty_siblings() {
+        let placeholder = JELLYFISH_PLACEHOLDER;
+        for nibble1 in 4..6 {
+            for nibble2 in 6..8 {
+                let (map, hashes) = nibble_map(&[nibble1, nibble2]);
+                let (hash1, hash2) = (hashes[0], hashes[1]);
+                let hash_x1 = Jellyfish::hash_inner(&hash1, &hash2);
+                let hash_x2 = Jellyfish::hash_inner(&placeholder, &hash_x1);
+                check_node_hash(&map, Jellyfish::hash_inner(&hash_x2, &placeholder));
+                for i in 0..4 {
+                    check_child_with_siblings(&map, i, None, &[placeholder, hash_x1]);
+                }
+                for i in 4..6 {
+                    let siblings = [placeholder, placeholder, hash2];
+                    check_child_with_siblings(&map, i, Some(nibble1), &siblings);
+                }
+                for i in 6..8 {
+                    let siblings = [placeholder, placeholder, hash1];
+                    check_child_with_siblings(&map, i, Some(nibble2), &siblings);
+                }
+                for i in 8..16 {
+                    check_child_with_siblings(&map, i, None, &[hash_x2]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_jellyfish_three_leaves() {
+        for nibble1 in 0..4 {
+            for nibble2 in 4..8 {
+                for nibble3 in 8..16 {
+                    let (map, hashes) = nibble_map(&[nibble1, nibble2, nibble3]);
+                    let (hash1, hash2, hash3) = (hashes[0], hashes[1], hashes[2]);
+                    let hash_x = Jellyfish::hash_inner(&hash1, &hash2);
+                    check_node_hash(&map, Jellyfish::hash_inner(&hash_x, &hash3));
+                    for i in 0..4 {
+                        check_child_with_siblings(&map, i, Some(nibble1), &[hash3, hash2]);
+                    }
+                    for i in 4..8 {
+                        check_child_with_siblings(&map, i, Some(nibble2), &[hash3, hash1]);
+                    }
+                    for i in 8..16 {
+                        check_child_with_siblings(&map, i, Some(nibble3), &[hash_x]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_profile<P: Profile>() {
+        let mut map = ProfileMap::<P>::new();
+        assert!(map.is_empty());
+        assert_eq!(map.merkle_root(), P::empty_hash(256));
+        let (_, proof) = map.get_with_proof(&[0x11; 32]);
+        assert_eq!(proof.leaf, None);
+        assert!(map.check_merkle_proof(&[0x11; 32], &[0; 32], &proof));
+
+        // A single leaf takes the place of the whole compacted tree.
+        assert_eq!(map.set(&[0x11; 32], [0xaa; 32]), [0; 32]);
+        let (value, proof) = map.get_with_proof(&[0x11; 32]);
+        assert_eq!(*value, [0xaa; 32]);
+        assert_eq!(proof.siblings.is_empty(), P::COMPACTED);
+        assert!(map.check_merkle_proof(&[0x11; 32], value, &proof));
+
+        map.set(&[0x22; 32], [0xbb; 32]);
+        map.set(&[0x33; 32], [0xcc; 32]);
+        assert_eq!(map.len(), 3);
+        let root = map.merkle_root();
+        let (value, proof) = map.get_with_proof(&[0x22; 32]);
+        assert!(check_merkle_proof::<P>(&root, &[0x22; 32], value, &proof));
+        // The proof of a key does not prove another key, nor the key unset.
+        assert!(!check_merkle_proof::<P>(&root, &[0x23; 32], value, &proof));
+        assert!(!check_merkle_proof::<P>(
+            &root,
+            &[0x22; 32],
+            &[0; 32],
+            &proof
+        ));
+        let mut tampered = proof.clone();
+        tampered.siblings[0][0] ^= 1;
+        assert!(!check_merkle_proof::<P>(
+            &root,
+            &[0x22; 32],
+            value,
+            &tampered
+        ));
+        let mut unset = proof.clone();
+        unset.leaf = None;
+        assert!(!check_merkle_proof::<P>(&root, &[0x22; 32], value, &unset));
+        let mut long = proof;
+        long.siblings.resize(257, [0; 32]);
+        assert!(!check_merkle_proof::<P>(&root, &[0x22; 32], value, &long));
+
+        // Unsetting the keys restores the empty root.
+        for key in [[0x11; 32], [0x22; 32], [0x33; 32]] {
+            assert_ne!(map.set(&key, [0; 32]), [0; 32]);
+        }
+        assert_eq!(map.merkle_root(), P::empty_hash(256));
+    }
+
+    #[test]
+    fn test_profiles() {
+        check_profile::<Native>();
+        check_profile::<Jellyfish>();
+    }
+}
@@ -0,0 +1,174 @@
+//! Python bindings, built as the `smt_map` extension module by maturin (see `pyproject.toml`).
+//!
+//! Keys, values, hashes and bitmaps are 32-byte `bytes` objects. Passing `bytes` of any other
+//! length raises `ValueError`, and passing other types raises `TypeError`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::{check_merkle_proof, Hash256, MerkleProof, SmtMap256};
+
+/// A map from 32-byte keys to 32-byte values backed by a sparse merkle tree. Every key has the
+/// value of 32 zero bytes until it is set.
+#[pyclass(name = "SmtMap256", module = "smt_map")]
+#[derive(Default)]
+struct PySmtMap256(SmtMap256);
+
+#[pymethods]
+impl PySmtMap256 {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of a key and returns the old value.
+    fn set<'py>(
+        &mut self,
+        py: Python<'py>,
+        key: &[u8],
+        value: &[u8],
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let old_value = self.0.set(&word(key, "key")?, word(value, "value")?);
+        Ok(PyBytes::new(py, &old_value))
+    }
+
+    /// Returns the value of a key.
+    fn get<'py>(&self, py: Python<'py>, key: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        Ok(PyBytes::new(py, self.0.get(&word(key, "key")?)))
+    }
+
+    /// Returns the value of a key and its merkle proof.
+    fn get_with_proof<'py>(
+        &self,
+        py: Python<'py>,
+        key: &[u8],
+    ) -> PyResult<(Bound<'py, PyBytes>, PyMerkleProof)> {
+        let (value, proof) = self.0.get_with_proof(&word(key, "key")?);
+        Ok((PyBytes::new(py, value), PyMerkleProof(proof)))
+    }
+
+    /// Returns the merkle root of the map.
+    fn merkle_root<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.0.merkle_root())
+    }
+
+    /// Checks the merkle proof of a key-value pair against the merkle root of the map.
+    fn check_merkle_proof(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        proof: &PyMerkleProof,
+    ) -> PyResult<bool> {
+        Ok(self
+            .0
+            .check_merkle_proof(&word(key, "key")?, &word(value, "value")?, &proof.0))
+    }
+
+    /// Returns the number of keys with a non-zero value.
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Merkle proof of a key-value pair: a bitmap of the non-default siblings along the path to the
+/// root, and the hashes of these siblings.
+#[pyclass(name = "MerkleProof", module = "smt_map", eq)]
+#[derive(Clone, PartialEq)]
+struct PyMerkleProof(MerkleProof);
+
+#[pymethods]
+impl PyMerkleProof {
+    /// A proof whose number of hashes does not match the bitmap can be constructed, but never
+    /// passes verification.
+    #[new]
+    fn new(bitmap: &[u8], hashes: Vec<Bound<'_, PyBytes>>) -> PyResult<Self> {
+        let bitmap = word(bitmap, "bitmap")?;
+        let hashes = hashes
+            .iter()
+            .map(|hash| word(hash.as_bytes(), "hash"))
+            .collect::<PyResult<_>>()?;
+        Ok(Self(MerkleProof { bitmap, hashes }))
+    }
+
+    /// Decodes a proof encoded by `to_bytes`.
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Self> {
+        MerkleProof::from_bytes(bytes)
+            .map(Self)
+            .ok_or_else(|| PyValueError::new_err("malformed merkle proof"))
+    }
+
+    /// Encodes the proof as the bitmap followed by the hashes.
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.to_bytes())
+    }
+
+    #[getter]
+    fn bitmap<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.bitmap)
+    }
+
+    #[getter]
+    fn hashes<'py>(&self, py: Python<'py>) -> Vec<Bound<'py, PyBytes>> {
+        self.0
+            .hashes
+            .iter()
+            .map(|hash| PyBytes::new(py, hash))
+            .collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MerkleProof(bitmap={}, hashes=[{}])",
+            hex(&self.0.bitmap),
+            self.0.hashes.iter().map(hex).collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+/// Checks the merkle proof of a key-value pair in the map with the given merkle root.
+#[pyfunction(name = "check_merkle_proof")]
+fn py_check_merkle_proof(
+    root: &[u8],
+    key: &[u8],
+    value: &[u8],
+    proof: &PyMerkleProof,
+) -> PyResult<bool> {
+    Ok(check_merkle_proof(
+        &word(root, "root")?,
+        &word(key, "key")?,
+        &word(value, "value")?,
+        &proof.0,
+    ))
+}
+
+#[pymodule]
+fn smt_map(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PySmtMap256>()?;
+    module.add_class::<PyMerkleProof>()?;
+    module.add_function(wrap_pyfunction!(py_check_merkle_proof, module)?)?;
+    Ok(())
+}
+
+fn word(bytes: &[u8], name: &str) -> PyResult<Hash256> {
+    if bytes.len() != 32 {
+        return Err(PyValueError::new_err(format!(
+            "{} must be 32 bytes, not {}",
+            name,
+            bytes.len()
+        )));
+    }
+    let mut word = [0; 32];
+    word.copy_from_slice(bytes);
+    Ok(word)
+}
+
+// Formats like Python's `bytes.hex()`.
+fn hex(bytes: &Hash256) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,427 @@
+//! A small HTTP/JSON server answering queries about a `PersistentSmtMap`.
+//!
+//! Endpoints (keys and values are hex words, left-padded with zeros if shorter than 32 bytes):
+//!
+//! * `GET /root`: `{"root": <hex>}`
+//! * `GET /value/{key}`: `{"key": <hex>, "value": <hex>}`
+//! * `GET /proof/{key}`: `{"key": <hex>, "value": <hex>, "root": <hex>, "proof": <hex>}`, where
+//!   `proof` is encoded by `MerkleProof::to_bytes`.
+//! * `POST /batch`: sets the values of several keys atomically. The request needs the header
+//!   `Authorization: Bearer <token>` and the body `{"updates": [{"key": <hex>, "value": <hex>},
+//!   ...]}`. Responds with `{"root": <hex>}`. Once an update fails to persist, the next ones are
+//!   refused with status 503 until the server is restarted.
+//!
+//! Errors are responded with a non-2xx status and `{"error": <message>}`.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::Duration;
+use std::{eprintln, format, thread};
+
+use serde_json::{json, Value as Json};
+
+use crate::persist::PersistentSmtMap;
+use crate::text::{self, to_hex};
+use crate::{Hash256, Key, Value};
+
+/// Maximum size of a request body.
+pub const MAX_BODY_LEN: usize = 16 << 20;
+
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Maximum length of the request line and of each header line, including the line break.
+const MAX_LINE_LEN: usize = 8 << 10;
+
+const MAX_HEADERS: usize = 64;
+
+// Maximum number of connections handled at once.
+const MAX_CONNECTIONS: usize = 64;
+
+// Delay before accepting again after a failure, which is often a lack of file descriptors.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Serves a `PersistentSmtMap` over HTTP.
+pub struct ProofServer {
+    map: RwLock<PersistentSmtMap>,
+
+    // Bearer token required by the update endpoint.
+    token: String,
+
+    // Set once an update failed to persist. The files of the map may then end with a torn record,
+    // so further updates are refused until the map is reopened.
+    read_only: AtomicBool,
+
+    // Number of connections being handled, and its signal of a connection being closed.
+    connections: Mutex<usize>,
+    connection_closed: Condvar,
+}
+
+// A connection counted in `ProofServer::connections` until dropped.
+struct ConnectionSlot(Arc<ProofServer>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        *self.0.connections.lock().unwrap() -= 1;
+        self.0.connection_closed.notify_one();
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+impl ProofServer {
+    pub fn new(map: PersistentSmtMap, token: String) -> Self {
+        Self {
+            map: RwLock::new(map),
+            token,
+            read_only: AtomicBool::new(false),
+            connections: Mutex::new(0),
+            connection_closed: Condvar::new(),
+        }
+    }
+
+    /// Accepts connections on `listener` forever, handling each on its own thread. Once 64
+    /// connections are being handled, the next ones wait in the backlog of `listener`. Failures to
+    /// accept a connection are printed to the standard error.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("cannot accept a connection: {}", e);
+                    thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            let slot = self.acquire_connection_slot();
+            let spawned = thread::Builder::new().spawn(move || {
+                // The connection is closed on errors; there is nobody else to report them to.
+                let _ = slot.0.handle_connection(stream);
+            });
+            if let Err(e) = spawned {
+                eprintln!("cannot handle a connection: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // Waits until less than `MAX_CONNECTIONS` connections are being handled, and counts one more.
+    fn acquire_connection_slot(self: &Arc<Self>) -> ConnectionSlot {
+        let mut connections = self.connections.lock().unwrap();
+        while *connections >= MAX_CONNECTIONS {
+            connections = self.connection_closed.wait(connections).unwrap();
+        }
+        *connections += 1;
+        ConnectionSlot(self.clone())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        self.handle(&stream, &stream)
+    }
+
+    fn handle<R: Read, W: Write>(&self, stream: R, response: W) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let (status, body) = match read_head(&mut reader)? {
+            // Updates are authorized before their body is read, so that no one without the token
+            // can make the server read up to `MAX_BODY_LEN` bytes.
+            Ok((request, _)) if is_update(&request) && !self.is_authorized(&request) => {
+                error(401, "unauthorized".to_string())
+            }
+            Ok((mut request, content_length)) => {
+                request.body = alloc::vec![0; content_length];
+                reader.read_exact(&mut request.body)?;
+                self.route(&request)
+            }
+            Err(message) => error(400, message),
+        };
+        write_response(response, status, &body)
+    }
+
+    fn route(&self, request: &Request) -> (u16, Json) {
+        match (request.method.as_str(), segments(&request.path).as_slice()) {
+            ("GET", ["root"]) => {
+                let map = self.map.read().unwrap();
+                (200, json!({ "root": to_hex(map.merkle_root()) }))
+            }
+            ("GET", ["value", key]) => match parse_word(key) {
+                Ok(key) => {
+                    let map = self.map.read().unwrap();
+                    let value = map.get(&key);
+                    (200, json!({ "key": to_hex(&key), "value": to_hex(value) }))
+                }
+                Err(message) => error(400, message),
+            },
+            ("GET", ["proof", key]) => match parse_word(key) {
+                Ok(key) => {
+                    let map = self.map.read().unwrap();
+                    let (value, proof) = map.get_with_proof(&key);
+                    let body = json!({
+                        "key": to_hex(&key),
+                        "value": to_hex(value),
+                        "root": to_hex(map.merkle_root()),
+                        "proof": to_hex(&proof.to_bytes()),
+                    });
+                    (200, body)
+                }
+                Err(message) => error(400, message),
+            },
+            ("POST", ["batch"]) => self.update(request),
+            (_, ["root"]) | (_, ["value", _]) | (_, ["proof", _]) | (_, ["batch"]) => {
+                error(405, "method not allowed".to_string())
+            }
+            _ => error(404, "not found".to_string()),
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        request
+            .authorization
+            .as_ref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+    }
+
+    // The request must be authorized.
+    fn update(&self, request: &Request) -> (u16, Json) {
+        let updates = match parse_updates(&request.body) {
+            Ok(updates) => updates,
+            Err(message) => return error(400, message),
+        };
+        let mut map = self.map.write().unwrap();
+        if self.read_only.load(Ordering::SeqCst) {
+            return error(
+                503,
+                "updates are disabled after a failure to persist one".to_string(),
+            );
+        }
+        match map.set_batch(&updates) {
+            Ok(()) => (200, json!({ "root": to_hex(map.merkle_root()) })),
+            Err(e) => {
+                self.read_only.store(true, Ordering::SeqCst);
+                error(500, format!("failed to persist the update: {}", e))
+            }
+        }
+    }
+}
+
+// Splits the path of a request into its segments.
+fn segments(path: &str) -> Vec<&str> {
+    path.trim_start_matches('/').split('/').collect()
+}
+
+fn is_update(request: &Request) -> bool {
+    request.method == "POST" && segments(&request.path) == ["batch"]
+}
+
+// Reads the request line and the headers of a request, and returns the request without its body
+// along with the length of the body. The inner error is a message about a malformed request.
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Result<(Request, usize), String>> {
+    let mut line = String::new();
+    if let Err(message) = read_line(reader, &mut line)? {
+        return Ok(Err(message));
+    }
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (method, path) = match parts.as_slice() {
+        [method, path, _version] => (method.to_string(), path.to_string()),
+        _ => return Ok(Err("malformed request line".to_string())),
+    };
+
+    let mut authorization = None;
+    let mut content_length = 0;
+    for i in 0.. {
+        match read_line(reader, &mut line)? {
+            Ok(0) => return Ok(Err("unexpected end of headers".to_string())),
+            Ok(_) => {}
+            Err(message) => return Ok(Err(message)),
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if i == MAX_HEADERS {
+            return Ok(Err("too many headers".to_string()));
+        }
+        let (name, value) = match header.split_once(':') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+            None => return Ok(Err("malformed header".to_string())),
+        };
+        match name.as_str() {
+            "authorization" => authorization = Some(value.to_string()),
+            "content-length" => match value.parse::<usize>() {
+                Ok(len) if len <= MAX_BODY_LEN => content_length = len,
+                _ => return Ok(Err("invalid content length".to_string())),
+            },
+            _ => {}
+        }
+    }
+
+    let request = Request {
+        method,
+        path,
+        authorization,
+        body: Vec::new(),
+    };
+    Ok(Ok((request, content_length)))
+}
+
+// Reads a line into `line`, replacing its content. Returns the length of the line, which is 0 at
+// the end of the stream. The inner error is a message about a line longer than `MAX_LINE_LEN`.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<Result<usize, String>> {
+    line.clear();
+    let len = reader.take(MAX_LINE_LEN as u64).read_line(line)?;
+    if len == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Ok(Err("line too long".to_string()));
+    }
+    Ok(Ok(len))
+}
+
+fn write_response<W: Write>(mut stream: W, status: u16, body: &Json) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+fn error(status: u16, message: String) -> (u16, Json) {
+    (status, json!({ "error": message }))
+}
+
+fn parse_updates(body: &[u8]) -> Result<Vec<(Key, Value)>, String> {
+    let json: Json = serde_json::from_slice(body).map_err(|e| format!("invalid JSON: {}", e))?;
+    let updates = json
+        .get("updates")
+        .and_then(Json::as_array)
+        .ok_or_else(|| "expected an `updates` array".to_string())?;
+    updates
+        .iter()
+        .map(|update| {
+            let field = |name: &str| {
+                update
+                    .get(name)
+                    .and_then(Json::as_str)
+                    .ok_or_else(|| format!("expected a `{}` string in each update", name))
+                    .and_then(parse_word)
+            };
+            Ok((field("key")?, field("value")?))
+        })
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn parse_word(s: &str) -> Result<Hash256, String> {
+    text::parse_word(s).ok_or_else(|| format!("`{}` is not a 32-byte hex word", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let request =
+            "POST /batch HTTP/1.1\r\nAuthorization: Bearer t\r\nContent-Length: 2\r\n\r\n{}";
+        let (request, content_length) = read_head(&mut request.as_bytes()).unwrap().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("POST", "/batch")
+        );
+        assert_eq!(request.authorization.as_deref(), Some("Bearer t"));
+        assert_eq!(content_length, 2);
+
+        // The limits on lines and headers.
+        let long_path = "/".repeat(MAX_LINE_LEN);
+        let request = format!("GET {} HTTP/1.1\r\n\r\n", long_path);
+        assert_eq!(
+            read_head(&mut request.as_bytes()).unwrap().err().unwrap(),
+            "line too long"
+        );
+        let request = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "x".repeat(MAX_LINE_LEN));
+        assert_eq!(
+            read_head(&mut request.as_bytes()).unwrap().err().unwrap(),
+            "line too long"
+        );
+        let headers = "X: x\r\n".repeat(MAX_HEADERS);
+        let request = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+        assert!(read_head(&mut request.as_bytes()).unwrap().is_ok());
+        let request = format!("GET / HTTP/1.1\r\n{}X: x\r\n\r\n", headers);
+        assert_eq!(
+            read_head(&mut request.as_bytes()).unwrap().err().unwrap(),
+            "too many headers"
+        );
+        let request = "GET / HTTP/1.1\r\nX: x\r\n";
+        assert_eq!(
+            read_head(&mut request.as_bytes()).unwrap().err().unwrap(),
+            "unexpected end of headers"
+        );
+    }
+
+    #[test]
+    fn test_authorization_before_body() {
+        let dir = std::env::temp_dir().join(format!("smt_map_server_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let server = ProofServer::new(PersistentSmtMap::open(&dir).unwrap(), "t".to_string());
+        let respond = |request: &str| {
+            let mut response = Vec::new();
+            server
+                .handle(request.as_bytes(), &mut response)
+                .map(|()| response)
+        };
+
+        // Without the token, the missing body is not even read.
+        let head = format!(
+            "POST /batch HTTP/1.1\r\nContent-Length: {}\r\n",
+            MAX_BODY_LEN
+        );
+        let response = respond(&format!("{}Authorization: Bearer x\r\n\r\n", head)).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 401 "));
+        let response = respond(&format!("{}\r\n", head)).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 401 "));
+        let error = respond(&format!("{}Authorization: Bearer t\r\n\r\n", head)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let body = r#"{"updates": [{"key": "1", "value": "2"}]}"#;
+        let request = format!(
+            "POST /batch HTTP/1.1\r\nAuthorization: Bearer t\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        assert!(respond(&request).unwrap().starts_with(b"HTTP/1.1 200 "));
+        let map = server.map.read().unwrap();
+        assert_eq!(
+            map.get(&text::parse_word("1").unwrap()),
+            &text::parse_word("2").unwrap()
+        );
+        drop(map);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! Insert-only set of 256-bit items, such as the nullifiers of a privacy protocol.
+//!
+//! The set is an `SmtMap256` in which the items have the value `PRESENT` and all other keys the
+//! default value. Membership and absence are thus proved with ordinary `MerkleProof`s of the two
+//! values. Items can never be removed, and inserting an item twice is rejected.
+
+use alloc::collections::btree_set::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{check_merkle_proof, compute_merkle_root, Hash256, Key, MerkleProof, SmtMap256, Value};
+
+/// The value of the items of a set in the underlying `SmtMap256`: one, in big-endian.
+pub const PRESENT: Value = {
+    let mut value = [0; 32];
+    value[31] = 1;
+    value
+};
+
+/// The item is already in the set.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AlreadyPresent(pub Key);
+
+impl fmt::Display for AlreadyPresent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("item already present: 0x")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AlreadyPresent {}
+
+/// Proof that a batch of insertions turned a set with root `old_root` into one with root
+/// `new_root`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InsertionWitness {
+    pub old_root: Hash256,
+
+    pub new_root: Hash256,
+
+    /// The inserted items in order, each with its absence proof in the set right before its
+    /// insertion. The siblings do not change when an item is inserted, so the proof also gives
+    /// the root right after the insertion.
+    pub insertions: Vec<(Key, MerkleProof)>,
+}
+
+impl InsertionWitness {
+    /// Checks that every item was absent when inserted, and that inserting them in order leads
+    /// from `old_root` to `new_root`.
+    pub fn verify(&self) -> bool {
+        let mut root = self.old_root;
+        for (item, proof) in &self.insertions {
+            if !check_absence(&root, item, proof) {
+                return false;
+            }
+            root = match compute_merkle_root(item, &PRESENT, proof.into()) {
+                Some(new_root) => new_root,
+                None => return false,
+            };
+        }
+        root == self.new_root
+    }
+}
+
+/// Insert-only set of 256-bit items backed by an `SmtMap256`.
+#[derive(Clone, Default)]
+pub struct SmtSet256 {
+    map: SmtMap256,
+}
+
+impl SmtSet256 {
+    /// Returns an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts an item. Fails if the item is already in the set.
+    pub fn insert(&mut self, item: &Key) -> Result<(), AlreadyPresent> {
+        if self.contains(item) {
+            return Err(AlreadyPresent(*item));
+        }
+        self.map.set(item, PRESENT);
+        Ok(())
+    }
+
+    /// Inserts a batch of items in order, and returns the witness of the transition. Fails without
+    /// inserting anything if some item is already in the set or occurs twice in the batch.
+    pub fn insert_batch(&mut self, items: &[Key]) -> Result<InsertionWitness, AlreadyPresent> {
+        let mut seen = BTreeSet::new();
+        for item in items {
+            if self.contains(item) || !seen.insert(item) {
+                return Err(AlreadyPresent(*item));
+            }
+        }
+
+        let old_root = *self.merkle_root();
+        let mut insertions = Vec::with_capacity(items.len());
+        for item in items {
+            insertions.push((*item, self.map.get_with_proof(item).1));
+            self.map.set(item, PRESENT);
+        }
+        Ok(InsertionWitness {
+            old_root,
+            new_root: *self.merkle_root(),
+            insertions,
+        })
+    }
+
+    /// Whether the item is in the set.
+    pub fn contains(&self, item: &Key) -> bool {
+        *self.map.get(item) == PRESENT
+    }
+
+    /// Returns the proof that the item is in the set, or `None` if it is not.
+    pub fn prove_membership(&self, item: &Key) -> Option<MerkleProof> {
+        let (value, proof) = self.map.get_with_proof(item);
+        if *value == PRESENT {
+            Some(proof)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the proof that the item is not in the set, or `None` if it is.
+    pub fn prove_absence(&self, item: &Key) -> Option<MerkleProof> {
+        let (value, proof) = self.map.get_with_proof(item);
+        if *value == [0; 32] {
+            Some(proof)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the merkle root of the underlying `SmtMap256`.
+    pub fn merkle_root(&self) -> &Hash256 {
+        self.map.merkle_root()
+    }
+
+    /// Returns the number of items.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether the set has no items.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// Checks the proof that an item is in the set with the given merkle root.
+pub fn check_membership(merkle_root: &Hash256, item: &Key, proof: &MerkleProof) -> bool {
+    check_merkle_proof(merkle_root, item, &PRESENT, proof)
+}
+
+/// Checks the proof that an item is not in the set with the given merkle root.
+pub fn check_absence(merkle_root: &Hash256, item: &Key, proof: &MerkleProof) -> bool {
+    check_merkle_proof(merkle_root, item, &[0; 32], proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut set = SmtSet256::new();
+        let empty_root = *set.merkle_root();
+        let (item1, item2) = ([1; 32], [2; 32]);
+
+        let proof = set.prove_absence(&item1).unwrap();
+        assert!(check_absence(&empty_root, &item1, &proof));
+        assert!(set.prove_membership(&item1).is_none());
+
+        assert_eq!(set.insert(&item1), Ok(()));
+        assert_eq!(set.insert(&item1), Err(AlreadyPresent(item1)));
+        assert!(set.contains(&item1));
+        assert!(!set.contains(&item2));
+        assert_eq!(set.len(), 1);
+
+        let root = *set.merkle_root();
+        let proof = set.prove_membership(&item1).unwrap();
+        assert!(check_membership(&root, &item1, &proof));
+        assert!(!check_absence(&root, &item1, &proof));
+        assert!(set.prove_absence(&item1).is_none());
+
+        let proof = set.prove_absence(&item2).unwrap();
+        assert!(check_absence(&root, &item2, &proof));
+        assert!(!check_membership(&root, &item2, &proof));
+        assert!(!check_absence(&empty_root, &item2, &proof));
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let mut set = SmtSet256::new();
+        set.insert(&[1; 32]).unwrap();
+        let root = *set.merkle_root();
+
+        // Rejected batches leave the set unchanged.
+        assert_eq!(
+            set.insert_batch(&[[2; 32], [1; 32]]),
+            Err(AlreadyPresent([1; 32]))
+        );
+        assert_eq!(
+            set.insert_batch(&[[2; 32], [3; 32], [2; 32]]),
+            Err(AlreadyPresent([2; 32]))
+        );
+        assert_eq!(*set.merkle_root(), root);
+        assert!(!set.contains(&[2; 32]));
+
+        let items = [[2; 32], [0xff; 32], [0; 32]];
+        let witness = set.insert_batch(&items).unwrap();
+        assert_eq!(witness.old_root, root);
+        assert_eq!(witness.new_root, *set.merkle_root());
+        assert!(witness.verify());
+        assert_eq!(set.len(), 4);
+
+        // The same items inserted one by one lead to the same root.
+        let mut other = SmtSet256::new();
+        for item in [[1; 32], [2; 32], [0xff; 32], [0; 32]] {
+            other.insert(&item).unwrap();
+        }
+        assert_eq!(other.merkle_root(), set.merkle_root());
+
+        // Tampered witnesses.
+        let mut wrong = witness.clone();
+        wrong.new_root = root;
+        assert!(!wrong.verify());
+        let mut wrong = witness.clone();
+        wrong.insertions.swap(0, 1);
+        assert!(!wrong.verify());
+        let mut wrong = witness.clone();
+        wrong.insertions[1].0 = [1; 32];
+        assert!(!wrong.verify());
+        let mut wrong = witness;
+        wrong.insertions.pop();
+        assert!(!wrong.verify());
+
+        let empty = set.insert_batch(&[]).unwrap();
+        assert_eq!(empty.old_root, empty.new_root);
+        assert!(empty.verify());
+    }
+}
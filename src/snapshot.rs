@@ -0,0 +1,336 @@
+//! Canonical binary snapshot of a whole `SmtMap256`.
+//!
+//! Layout (integers are little-endian):
+//!
+//! | Size        | Content                                                                  |
+//! |-------------|--------------------------------------------------------------------------|
+//! | 4           | Magic bytes `SMTM`                                                        |
+//! | 2           | Format version, currently 1                                              |
+//! | 2           | Hash scheme, see `HASH_SCHEME_KECCAK256`                                 |
+//! | 8           | Number of entries `n`                                                    |
+//! | `64 * n`    | The entries as (key, value), keys strictly increasing, values non-zero   |
+//! | 32          | Merkle root                                                              |
+//!
+//! A map has exactly one snapshot. Readers recompute the merkle root from the entries and reject
+//! the snapshot if it does not match the stored one.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{Hash256, Key, SmtMap256, Value};
+
+const MAGIC: [u8; 4] = *b"SMTM";
+
+/// The current version of the snapshot format.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// Hash scheme of `SmtMap256`: the hash of a leaf is its value, and the hash of an inner node is
+/// the keccak-256 of the concatenated hashes of its children.
+pub const HASH_SCHEME_KECCAK256: u16 = 1;
+
+const HEADER_LEN: usize = 16;
+
+/// Reason for rejecting a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The snapshot does not start with the magic bytes.
+    BadMagic,
+
+    UnsupportedVersion(u16),
+
+    UnsupportedHashScheme(u16),
+
+    /// The snapshot ends before the merkle root.
+    Truncated,
+
+    /// There is data after the merkle root.
+    TrailingData,
+
+    /// The keys are not strictly increasing, or some value is zero.
+    NonCanonical,
+
+    /// The merkle root recomputed from the entries differs from the stored one.
+    RootMismatch,
+
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => f.write_str("not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::UnsupportedHashScheme(scheme) => {
+                write!(f, "unsupported hash scheme {}", scheme)
+            }
+            SnapshotError::Truncated => f.write_str("truncated snapshot"),
+            SnapshotError::TrailingData => f.write_str("trailing data after snapshot"),
+            SnapshotError::NonCanonical => f.write_str("non-canonical snapshot"),
+            SnapshotError::RootMismatch => f.write_str("merkle root mismatch"),
+            #[cfg(feature = "std")]
+            SnapshotError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl SmtMap256 {
+    /// Returns the snapshot of the map.
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        let count = self.kvs.values().filter(|value| **value != [0; 32]).count();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + 64 * count + 32);
+        bytes.extend_from_slice(&header(count as u64));
+        for (key, value) in self.kvs.iter().filter(|(_, value)| **value != [0; 32]) {
+            bytes.extend_from_slice(key);
+            bytes.extend_from_slice(value);
+        }
+        bytes.extend_from_slice(self.merkle_root());
+        bytes
+    }
+
+    /// Restores a map from its snapshot.
+    pub fn from_snapshot(mut bytes: &[u8]) -> Result<Self, SnapshotError> {
+        // Check the length up front to avoid hashing the entries of a truncated snapshot.
+        if bytes.len() >= HEADER_LEN {
+            let mut count = [0; 8];
+            count.copy_from_slice(&bytes[8..HEADER_LEN]);
+            let expected_len = (u64::from_le_bytes(count) as u128) * 64 + (HEADER_LEN + 32) as u128;
+            if (bytes.len() as u128) < expected_len {
+                return Err(SnapshotError::Truncated);
+            }
+        }
+        let map = decode(|buf| {
+            if bytes.len() < buf.len() {
+                return Err(SnapshotError::Truncated);
+            }
+            let (head, tail) = bytes.split_at(buf.len());
+            buf.copy_from_slice(head);
+            bytes = tail;
+            Ok(())
+        })?;
+        if !bytes.is_empty() {
+            return Err(SnapshotError::TrailingData);
+        }
+        Ok(map)
+    }
+
+    /// Writes the snapshot of the map to `writer`.
+    #[cfg(feature = "std")]
+    pub fn write_snapshot<W: std::io::Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let count = self.kvs.values().filter(|value| **value != [0; 32]).count();
+        writer.write_all(&header(count as u64))?;
+        for (key, value) in self.kvs.iter().filter(|(_, value)| **value != [0; 32]) {
+            writer.write_all(key)?;
+            writer.write_all(value)?;
+        }
+        writer.write_all(self.merkle_root())?;
+        writer.flush()
+    }
+
+    /// Reads a snapshot from `reader` up to its end, and restores the map.
+    #[cfg(feature = "std")]
+    pub fn read_snapshot<R: std::io::Read>(mut reader: R) -> Result<Self, SnapshotError> {
+        let map = decode(|buf| {
+            reader.read_exact(buf).map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => SnapshotError::Truncated,
+                _ => SnapshotError::Io(e),
+            })
+        })?;
+        let mut extra = [0; 1];
+        loop {
+            match reader.read(&mut extra) {
+                Ok(0) => return Ok(map),
+                Ok(_) => return Err(SnapshotError::TrailingData),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(SnapshotError::Io(e)),
+            }
+        }
+    }
+}
+
+fn header(count: u64) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    header[6..8].copy_from_slice(&HASH_SCHEME_KECCAK256.to_le_bytes());
+    header[8..].copy_from_slice(&count.to_le_bytes());
+    header
+}
+
+// Decodes a snapshot, reading it with `read_exact`, which fills the given buffer.
+fn decode<F>(mut read_exact: F) -> Result<SmtMap256, SnapshotError>
+where
+    F: FnMut(&mut [u8]) -> Result<(), SnapshotError>,
+{
+    let mut header = [0; HEADER_LEN];
+    read_exact(&mut header)?;
+    if header[..4] != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let hash_scheme = u16::from_le_bytes([header[6], header[7]]);
+    if hash_scheme != HASH_SCHEME_KECCAK256 {
+        return Err(SnapshotError::UnsupportedHashScheme(hash_scheme));
+    }
+    let mut count = [0; 8];
+    count.copy_from_slice(&header[8..]);
+    let count = u64::from_le_bytes(count);
+
+    let mut map = SmtMap256::new();
+    let mut prev_key: Option<Key> = None;
+    for _ in 0..count {
+        let (mut key, mut value): (Key, Value) = ([0; 32], [0; 32]);
+        read_exact(&mut key)?;
+        read_exact(&mut value)?;
+        if prev_key.is_some_and(|prev| prev >= key) || value == [0; 32] {
+            return Err(SnapshotError::NonCanonical);
+        }
+        map.set(&key, value);
+        prev_key = Some(key);
+    }
+
+    let mut root: Hash256 = [0; 32];
+    read_exact(&mut root)?;
+    if *map.merkle_root() != root {
+        return Err(SnapshotError::RootMismatch);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut smt = SmtMap256::new();
+        for i in 1..=20_u8 {
+            smt.set(&[i.wrapping_mul(101); 32], [i; 32]);
+        }
+        // Keys reset to zero are not part of the snapshot.
+        smt.set(&[0x33; 32], [1; 32]);
+        smt.set(&[0x33; 32], [0; 32]);
+        let bytes = smt.to_snapshot();
+        assert_eq!(bytes.len(), HEADER_LEN + 64 * 20 + 32);
+        assert_eq!(&bytes[..8], b"SMTM\x01\x00\x01\x00");
+        assert_eq!(&bytes[8..16], &20_u64.to_le_bytes());
+        assert_eq!(&bytes[bytes.len() - 32..], smt.merkle_root());
+
+        let restored = SmtMap256::from_snapshot(&bytes).unwrap();
+        assert_eq!(restored.merkle_root(), smt.merkle_root());
+        assert_eq!(restored.diff(&smt).count(), 0);
+        assert_eq!(restored.to_snapshot(), bytes);
+
+        let empty = SmtMap256::new().to_snapshot();
+        assert_eq!(empty.len(), HEADER_LEN + 32);
+        let restored = SmtMap256::from_snapshot(&empty).unwrap();
+        assert_eq!(restored.merkle_root(), SmtMap256::new().merkle_root());
+    }
+
+    #[test]
+    fn test_snapshot_rejected() {
+        let mut smt = SmtMap256::new();
+        for i in 1..=20_u8 {
+            smt.set(&[i.wrapping_mul(101); 32], [i; 32]);
+        }
+        let bytes = smt.to_snapshot();
+
+        // Truncated at every offset.
+        for len in 0..bytes.len() {
+            assert!(matches!(
+                SmtMap256::from_snapshot(&bytes[..len]),
+                Err(SnapshotError::Truncated)
+            ));
+        }
+
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(matches!(
+            SmtMap256::from_snapshot(&extended),
+            Err(SnapshotError::TrailingData)
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[0] = b'X';
+        assert!(matches!(
+            SmtMap256::from_snapshot(&corrupted),
+            Err(SnapshotError::BadMagic)
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[4] = 2;
+        assert!(matches!(
+            SmtMap256::from_snapshot(&corrupted),
+            Err(SnapshotError::UnsupportedVersion(2))
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[6] = 9;
+        assert!(matches!(
+            SmtMap256::from_snapshot(&corrupted),
+            Err(SnapshotError::UnsupportedHashScheme(9))
+        ));
+
+        // A flipped bit in a value, or in the root.
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_LEN + 64 * 3 + 40] ^= 4;
+        assert!(matches!(
+            SmtMap256::from_snapshot(&corrupted),
+            Err(SnapshotError::RootMismatch)
+        ));
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            SmtMap256::from_snapshot(&corrupted),
+            Err(SnapshotError::RootMismatch)
+        ));
+
+        // Swapped entries.
+        let mut corrupted = bytes.clone();
+        let (first, second) = corrupted[HEADER_LEN..HEADER_LEN + 128].split_at_mut(64);
+        first.swap_with_slice(second);
+        assert!(matches!(
+            SmtMap256::from_snapshot(&corrupted),
+            Err(SnapshotError::NonCanonical)
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_snapshot_io() {
+        let mut smt = SmtMap256::new();
+        for i in 1..=20_u8 {
+            smt.set(&[i.wrapping_mul(101); 32], [i; 32]);
+        }
+        let mut bytes = Vec::new();
+        smt.write_snapshot(&mut bytes).unwrap();
+        assert_eq!(bytes, smt.to_snapshot());
+
+        let restored = SmtMap256::read_snapshot(&bytes[..]).unwrap();
+        assert_eq!(restored.merkle_root(), smt.merkle_root());
+        assert!(matches!(
+            SmtMap256::read_snapshot(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)
+        ));
+        bytes.push(0);
+        assert!(matches!(
+            SmtMap256::read_snapshot(&bytes[..]),
+            Err(SnapshotError::TrailingData)
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+//! Storage of the key-values and node hashes of an `SmtMap256`.
+//!
+//! A map is generic over a `Storage`, which picks the kind of `MapStore` holding its key-values
+//! and its node hashes. `BTreeStorage` is the default, and keeps the keys in order, which
+//! chunking, snapshots and persistence rely on. With the `std` feature, `HashStorage` trades the
+//! order for faster lookups.
+
+use alloc::collections::btree_map::{self, BTreeMap};
+use core::hash::Hash;
+
+#[cfg(feature = "std")]
+use std::collections::{hash_map, HashMap};
+
+/// A mutable map from `K` to `V`.
+pub trait MapStore<K, V>: Default + Clone {
+    type Iter<'a>: Iterator<Item = (&'a K, &'a V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    fn get(&self, key: &K) -> Option<&V>;
+
+    /// Returns the old value of the key, if any.
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+
+    /// Returns the old value of the key, if any.
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the entries, in an order defined by the store.
+    fn iter(&self) -> Self::Iter<'_>;
+}
+
+/// A kind of `MapStore`, instantiated for each type of entries an `SmtMap256` keeps.
+pub trait Storage {
+    type Map<K: Ord + Hash + Clone, V: Clone>: MapStore<K, V>;
+}
+
+/// Stores entries in `BTreeMap`s, iterated in the order of the keys.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BTreeStorage;
+
+impl Storage for BTreeStorage {
+    type Map<K: Ord + Hash + Clone, V: Clone> = BTreeMap<K, V>;
+}
+
+impl<K: Ord + Clone, V: Clone> MapStore<K, V> for BTreeMap<K, V> {
+    type Iter<'a>
+        = btree_map::Iter<'a, K, V>
+    where
+        K: 'a,
+        V: 'a;
+
+    fn get(&self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTreeMap::insert(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        BTreeMap::iter(self)
+    }
+}
+
+/// Stores entries in `HashMap`s, iterated in arbitrary order.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct HashStorage;
+
+#[cfg(feature = "std")]
+impl Storage for HashStorage {
+    type Map<K: Ord + Hash + Clone, V: Clone> = HashMap<K, V>;
+}
+
+#[cfg(feature = "std")]
+impl<K: Hash + Eq + Clone, V: Clone> MapStore<K, V> for HashMap<K, V> {
+    type Iter<'a>
+        = hash_map::Iter<'a, K, V>
+    where
+        K: 'a,
+        V: 'a;
+
+    fn get(&self, key: &K) -> Option<&V> {
+        HashMap::get(self, key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        HashMap::insert(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        HashMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        HashMap::iter(self)
+    }
+}
@@ -0,0 +1,387 @@
+//! Merkle sum tree: an `SmtMap256`-like map whose nodes commit to the sum of the amounts below
+//! them, for proofs of liabilities.
+//!
+//! Every node of the tree is a `SumNode` of a hash and a sum. A leaf has the amount of its key as
+//! both its hash and its sum. An inner node has the sum of the sums of its children, and the
+//! keccak-256 of `left hash || left sum || right hash || right sum` as its hash. The sum of the
+//! root is thus the total of all amounts, and the merkle proof of a key shows that its amount is
+//! included in the total.
+//!
+//! Amounts are unsigned 256-bit integers in big-endian. Updates which would make the total exceed
+//! `2^256 - 1` are rejected, and so are proofs whose sums overflow.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{bit_op, Hash256, Key, NodeIndex};
+
+/// An unsigned 256-bit integer in big-endian.
+pub type Amount = [u8; 32];
+
+/// A node of a merkle sum tree.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SumNode {
+    pub hash: Hash256,
+
+    /// The sum of the amounts of the leaves below the node.
+    pub sum: Amount,
+}
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_SUM_NODES: [SumNode; 257] = {
+        // The element at index `i` is the root of a subtree with `2^i` default leaves.
+        let mut nodes = [SumNode { hash: [0; 32], sum: [0; 32] }; 257];
+        for i in 1..=256 {
+            nodes[i] = merge_nodes(&nodes[i - 1], &nodes[i - 1]).unwrap();
+        }
+        nodes
+    };
+}
+
+/// The total of the amounts would exceed `2^256 - 1`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Overflow;
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("sum overflow")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Overflow {}
+
+/// Merkle proof of a certain triple (root, key, amount) in a merkle sum tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SumMerkleProof {
+    /// Whether the siblings along the path to the root are non-default nodes.
+    pub bitmap: [u8; 32],
+
+    pub siblings: Vec<SumNode>,
+}
+
+/// Merkle sum tree map from 256-bit keys to 256-bit amounts. Initially every key has an amount
+/// of zero.
+#[derive(Clone, Default)]
+pub struct SmtSumMap256 {
+    kvs: BTreeMap<Key, Amount>,
+
+    // Both leaf and inner nodes which are not default.
+    nodes: BTreeMap<NodeIndex, SumNode>,
+}
+
+impl SmtSumMap256 {
+    /// Returns a new map where all keys have an amount of zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the amount of a key. Returns the old amount of the key, or leaves the map unchanged
+    /// and returns `Overflow` if the new total would not fit in 256 bits.
+    pub fn set(&mut self, key: &Key, amount: Amount) -> Result<Amount, Overflow> {
+        let old_amount = *self.get(key);
+        // The sum of every node is at most the total, so no other sum can overflow.
+        let others = checked_sub(&self.total(), &old_amount).unwrap();
+        checked_add(&others, &amount).ok_or(Overflow)?;
+
+        let mut index = NodeIndex::leaf(*key);
+        let mut node = SumNode {
+            hash: amount,
+            sum: amount,
+        };
+        self.update_node(&index, &node);
+        while !index.is_root() {
+            let sibling = *self.get_node(&index.sibling().unwrap());
+            node = if index.is_left() {
+                merge_nodes(&node, &sibling)
+            } else {
+                merge_nodes(&sibling, &node)
+            }
+            .unwrap();
+            index.move_up();
+            self.update_node(&index, &node);
+        }
+
+        if amount == [0; 32] {
+            self.kvs.remove(key);
+        } else {
+            self.kvs.insert(*key, amount);
+        }
+        Ok(old_amount)
+    }
+
+    /// Returns a reference to the amount of a key.
+    pub fn get(&self, key: &Key) -> &Amount {
+        self.kvs.get(key).unwrap_or(&[0; 32])
+    }
+
+    /// Returns a reference to the amount of the key with merkle proof.
+    pub fn get_with_proof(&self, key: &Key) -> (&Amount, SumMerkleProof) {
+        let mut bitmap = [0; 32];
+        let mut siblings = Vec::new();
+        let mut index = NodeIndex::leaf(*key);
+        for i in 0..256 {
+            if let Some(sibling) = self.nodes.get(&index.sibling().unwrap()) {
+                bit_op::set_bit(&mut bitmap, i);
+                siblings.push(*sibling);
+            }
+            index.move_up();
+        }
+        (self.get(key), SumMerkleProof { bitmap, siblings })
+    }
+
+    /// Returns the root of the tree, whose sum is the total of all amounts.
+    pub fn merkle_root(&self) -> &SumNode {
+        self.get_node(&NodeIndex::root())
+    }
+
+    /// Returns the total of all amounts.
+    pub fn total(&self) -> Amount {
+        self.merkle_root().sum
+    }
+
+    /// Returns the number of keys with non-zero amounts.
+    pub fn len(&self) -> usize {
+        self.kvs.len()
+    }
+
+    /// Whether every key has an amount of zero.
+    pub fn is_empty(&self) -> bool {
+        self.kvs.is_empty()
+    }
+
+    /// Check the merkle proof of a key-amount pair in this map. Returns whether the proof is
+    /// valid.
+    pub fn check_merkle_proof(&self, key: &Key, amount: &Amount, proof: &SumMerkleProof) -> bool {
+        check_sum_merkle_proof(self.merkle_root(), key, amount, proof)
+    }
+
+    fn get_node(&self, index: &NodeIndex) -> &SumNode {
+        self.nodes
+            .get(index)
+            .unwrap_or(&(*DEFAULT_SUM_NODES)[256 - index.depth])
+    }
+
+    fn update_node(&mut self, index: &NodeIndex, node: &SumNode) {
+        if (*DEFAULT_SUM_NODES)[256 - index.depth] == *node {
+            self.nodes.remove(index);
+        } else {
+            self.nodes.insert(index.clone(), *node);
+        }
+    }
+}
+
+/// Check the merkle proof of a key-amount pair in a merkle sum tree (specified by its root).
+/// Returns whether the proof is valid, which implies that `amount` is included in `root.sum`.
+pub fn check_sum_merkle_proof(
+    root: &SumNode,
+    key: &Key,
+    amount: &Amount,
+    proof: &SumMerkleProof,
+) -> bool {
+    let mut node = SumNode {
+        hash: *amount,
+        sum: *amount,
+    };
+    let mut iter = proof.siblings.iter();
+    for i in 0..256 {
+        let sibling = if !bit_op::get_bit(&proof.bitmap, i) {
+            &(*DEFAULT_SUM_NODES)[i]
+        } else if let Some(sibling) = iter.next() {
+            sibling
+        } else {
+            return false;
+        };
+
+        let merged = if bit_op::get_bit(key, 255 - i) {
+            merge_nodes(sibling, &node)
+        } else {
+            merge_nodes(&node, sibling)
+        };
+        node = match merged {
+            Some(merged) => merged,
+            None => return false,
+        };
+    }
+
+    iter.next().is_none() && node == *root
+}
+
+// Returns `None` if the sum overflows.
+fn merge_nodes(left: &SumNode, right: &SumNode) -> Option<SumNode> {
+    let sum = checked_add(&left.sum, &right.sum)?;
+    let mut hasher = tiny_keccak::Keccak::new_keccak256();
+    hasher.update(&left.hash);
+    hasher.update(&left.sum);
+    hasher.update(&right.hash);
+    hasher.update(&right.sum);
+    let mut hash = [0; 32];
+    hasher.finalize(&mut hash);
+    Some(SumNode { hash, sum })
+}
+
+fn checked_add(a: &Amount, b: &Amount) -> Option<Amount> {
+    let mut result = [0; 32];
+    let mut carry = false;
+    for i in (0..32).rev() {
+        let (sum, carry1) = a[i].overflowing_add(b[i]);
+        let (sum, carry2) = sum.overflowing_add(carry as u8);
+        result[i] = sum;
+        carry = carry1 || carry2;
+    }
+    if carry {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+fn checked_sub(a: &Amount, b: &Amount) -> Option<Amount> {
+    let mut result = [0; 32];
+    let mut borrow = false;
+    for i in (0..32).rev() {
+        let (diff, borrow1) = a[i].overflowing_sub(b[i]);
+        let (diff, borrow2) = diff.overflowing_sub(borrow as u8);
+        result[i] = diff;
+        borrow = borrow1 || borrow2;
+    }
+    if borrow {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(n: u128) -> Amount {
+        let mut amount = [0; 32];
+        amount[16..].copy_from_slice(&n.to_be_bytes());
+        amount
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        assert_eq!(checked_add(&amount(0xff), &amount(1)), Some(amount(0x100)));
+        assert_eq!(
+            checked_add(&amount(u128::MAX), &amount(1)),
+            Some({
+                let mut amount = [0; 32];
+                amount[15] = 1;
+                amount
+            })
+        );
+        assert_eq!(checked_add(&[0xff; 32], &amount(1)), None);
+        assert_eq!(checked_add(&[0xff; 32], &amount(0)), Some([0xff; 32]));
+        assert_eq!(checked_sub(&amount(0x100), &amount(1)), Some(amount(0xff)));
+        assert_eq!(checked_sub(&amount(1), &amount(2)), None);
+    }
+
+    #[test]
+    fn test_sum_map() {
+        let mut smt = SmtSumMap256::new();
+        let empty_root = *smt.merkle_root();
+        assert_eq!(empty_root, (*DEFAULT_SUM_NODES)[256]);
+        assert_eq!(empty_root.sum, [0; 32]);
+
+        let (key1, key2, key3) = ([1; 32], [2; 32], [0xff; 32]);
+        assert_eq!(smt.set(&key1, amount(100)), Ok([0; 32]));
+        assert_eq!(smt.set(&key2, amount(250)), Ok([0; 32]));
+        assert_eq!(smt.set(&key3, amount(7)), Ok([0; 32]));
+        assert_eq!(smt.total(), amount(357));
+        assert_eq!(smt.set(&key1, amount(40)), Ok(amount(100)));
+        assert_eq!(smt.total(), amount(297));
+        assert_eq!(smt.len(), 3);
+
+        for key in [key1, key2, key3, [3; 32]] {
+            let (value, proof) = smt.get_with_proof(&key);
+            assert!(smt.check_merkle_proof(&key, value, &proof));
+            assert!(!smt.check_merkle_proof(&key, &amount(1000), &proof));
+        }
+
+        // Resetting every amount restores the empty tree.
+        for key in [key1, key2, key3] {
+            smt.set(&key, [0; 32]).unwrap();
+        }
+        assert_eq!(*smt.merkle_root(), empty_root);
+        assert!(smt.is_empty());
+        assert!(smt.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_sum_map_overflow() {
+        let mut smt = SmtSumMap256::new();
+        let mut half = [0xff; 32];
+        half[0] = 0x7f;
+        smt.set(&[1; 32], half).unwrap();
+        smt.set(&[2; 32], half).unwrap();
+        let root = *smt.merkle_root();
+        assert_eq!(root.sum, {
+            let mut total = [0xff; 32];
+            total[31] = 0xfe;
+            total
+        });
+
+        // Overflowing updates leave the map unchanged.
+        assert_eq!(smt.set(&[3; 32], amount(2)), Err(Overflow));
+        assert_eq!(smt.set(&[3; 32], [0xff; 32]), Err(Overflow));
+        assert_eq!(*smt.merkle_root(), root);
+        assert_eq!(*smt.get(&[3; 32]), [0; 32]);
+
+        // Replacing an amount counts only the difference.
+        assert_eq!(smt.set(&[3; 32], amount(1)), Ok([0; 32]));
+        assert_eq!(smt.set(&[1; 32], amount(0)), Ok(half));
+        assert_eq!(smt.set(&[1; 32], [0xff; 32]), Err(Overflow));
+    }
+
+    #[test]
+    fn test_sum_merkle_proof_negative_cases() {
+        let mut smt = SmtSumMap256::new();
+        let key = [0x80; 32];
+        smt.set(&key, amount(10)).unwrap();
+        smt.set(&[0; 32], amount(20)).unwrap();
+        smt.set(&[0xff; 32], amount(30)).unwrap();
+        let root = *smt.merkle_root();
+        let (_, proof) = smt.get_with_proof(&key);
+        assert_eq!(proof.siblings.len(), 2);
+        assert!(check_sum_merkle_proof(&root, &key, &amount(10), &proof));
+
+        // A sibling claiming a smaller sum, which would hide liabilities.
+        let mut tampered = proof.clone();
+        tampered.siblings[0].sum = amount(0);
+        assert!(!check_sum_merkle_proof(&root, &key, &amount(10), &tampered));
+
+        // A root with a different total.
+        let wrong_root = SumNode {
+            sum: amount(59),
+            ..root
+        };
+        assert!(!check_sum_merkle_proof(
+            &wrong_root,
+            &key,
+            &amount(10),
+            &proof
+        ));
+
+        // A sibling whose sum overflows with the amount.
+        let mut overflowing = proof.clone();
+        overflowing.siblings[0].sum = [0xff; 32];
+        assert!(!check_sum_merkle_proof(
+            &root,
+            &key,
+            &amount(10),
+            &overflowing
+        ));
+
+        // Missing and extra siblings.
+        let mut missing = proof.clone();
+        missing.siblings.pop();
+        assert!(!check_sum_merkle_proof(&root, &key, &amount(10), &missing));
+        let mut extra = proof;
+        extra.siblings.push(root);
+        assert!(!check_sum_merkle_proof(&root, &key, &amount(10), &extra));
+    }
+}
@@ -0,0 +1,431 @@
+//! A protocol for rebuilding an `SmtMap256` from an untrusted peer, given only a trusted merkle
+//! root.
+//!
+//! The client walks the tree from the root down. For every non-default inner node it already
+//! trusts, it asks the peer for the hashes of the two children, and accepts them only if they
+//! merge into the hash of the node. Default subtrees are never requested, so the number of
+//! round trips depends only on the height of the tree and the number of non-default nodes.
+
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::fmt;
+
+use crate::{bit_op, merge_hashes, Hash256, NodeIndex, SmtMap256, DEFAULT_HASHES};
+
+/// Default maximum number of nodes asked for in one request.
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+
+/// Request for the child hashes of some inner nodes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SyncRequest {
+    nodes: Vec<NodeIndex>,
+}
+
+impl SyncRequest {
+    /// Number of nodes requested.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether no node is requested.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Encodes the request as the depth (1 byte) followed by the bit path (32 bytes) of each
+    /// requested node.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.nodes.len() * 33);
+        for node in &self.nodes {
+            bytes.push(node.depth as u8);
+            bytes.extend_from_slice(&node.bit_path);
+        }
+        bytes
+    }
+
+    /// Decodes a request encoded by `to_bytes`. Returns `None` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(33) {
+            return None;
+        }
+        let mut nodes = Vec::with_capacity(bytes.len() / 33);
+        for chunk in bytes.chunks(33) {
+            let depth = chunk[0] as usize;
+            let mut bit_path = [0; 32];
+            bit_path.copy_from_slice(&chunk[1..]);
+            if (depth..256).any(|i| bit_op::get_bit(&bit_path, i)) {
+                return None;
+            }
+            nodes.push(NodeIndex { bit_path, depth });
+        }
+        Some(Self { nodes })
+    }
+}
+
+/// Response to a `SyncRequest`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SyncResponse {
+    /// The (left, right) child hashes of each requested node, in the order of the request.
+    pub children: Vec<(Hash256, Hash256)>,
+}
+
+impl SyncResponse {
+    /// Encodes the response as the concatenation of all the child hashes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.children.len() * 64);
+        for (left, right) in &self.children {
+            bytes.extend_from_slice(left);
+            bytes.extend_from_slice(right);
+        }
+        bytes
+    }
+
+    /// Decodes a response encoded by `to_bytes`. Returns `None` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if !bytes.len().is_multiple_of(64) {
+            return None;
+        }
+        let children = bytes
+            .chunks(64)
+            .map(|chunk| {
+                let (mut left, mut right) = ([0; 32], [0; 32]);
+                left.copy_from_slice(&chunk[..32]);
+                right.copy_from_slice(&chunk[32..]);
+                (left, right)
+            })
+            .collect();
+        Some(Self { children })
+    }
+}
+
+/// A channel to a peer which answers `SyncRequest`s.
+pub trait SyncTransport {
+    type Error;
+
+    /// Sends `request` to the peer and waits for its response.
+    fn fetch(&mut self, request: &SyncRequest) -> Result<SyncResponse, Self::Error>;
+}
+
+/// Answers `SyncRequest`s from the content of a map.
+pub struct SyncServer<'a> {
+    map: &'a SmtMap256,
+}
+
+impl<'a> SyncServer<'a> {
+    pub fn new(map: &'a SmtMap256) -> Self {
+        Self { map }
+    }
+
+    /// Returns the child hashes of the requested nodes.
+    pub fn respond(&self, request: &SyncRequest) -> SyncResponse {
+        let children = request
+            .nodes
+            .iter()
+            .filter(|node| node.depth < 256)
+            .map(|node| {
+                (
+                    *self.map.get_hash(&node.left_child()),
+                    *self.map.get_hash(&node.right_child()),
+                )
+            })
+            .collect();
+        SyncResponse { children }
+    }
+}
+
+/// A transport which calls a `SyncServer` in the same process.
+pub struct InProcessTransport<'a> {
+    server: SyncServer<'a>,
+}
+
+impl<'a> InProcessTransport<'a> {
+    pub fn new(map: &'a SmtMap256) -> Self {
+        Self {
+            server: SyncServer::new(map),
+        }
+    }
+}
+
+impl<'a> SyncTransport for InProcessTransport<'a> {
+    type Error = Infallible;
+
+    fn fetch(&mut self, request: &SyncRequest) -> Result<SyncResponse, Self::Error> {
+        Ok(self.server.respond(request))
+    }
+}
+
+/// Reason for rejecting a `SyncResponse`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvalidResponse {
+    /// There is no outstanding request.
+    Unexpected,
+
+    /// The number of child hash pairs differs from the number of requested nodes.
+    LengthMismatch,
+
+    /// The child hashes of some node do not merge into the hash of the node.
+    HashMismatch,
+}
+
+/// Error of `SyncClient::run`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SyncError<E> {
+    Transport(E),
+    InvalidResponse(InvalidResponse),
+}
+
+impl fmt::Display for InvalidResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InvalidResponse::Unexpected => "unexpected response",
+            InvalidResponse::LengthMismatch => "wrong number of child hashes",
+            InvalidResponse::HashMismatch => "child hashes do not match",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidResponse {}
+
+impl<E: fmt::Display> fmt::Display for SyncError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncError::Transport(e) => write!(f, "transport error: {}", e),
+            SyncError::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for SyncError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SyncError::Transport(e) => Some(e),
+            SyncError::InvalidResponse(e) => Some(e),
+        }
+    }
+}
+
+/// State machine rebuilding an `SmtMap256` with a given merkle root from `SyncResponse`s.
+///
+/// Call `next_request` to get the request to send, and feed the peer's answer to
+/// `handle_response`, until `is_complete` returns true. A rejected response leaves the state
+/// unchanged, so the same request can be sent again, possibly to another peer.
+pub struct SyncClient {
+    batch_size: usize,
+
+    // Verified non-default inner nodes whose children have not been fetched yet.
+    pending: Vec<(NodeIndex, Hash256)>,
+
+    // The nodes of the outstanding request.
+    in_flight: Vec<(NodeIndex, Hash256)>,
+
+    // The verified part of the map.
+    map: SmtMap256,
+}
+
+impl SyncClient {
+    /// Returns a client which rebuilds the map with the given merkle root.
+    pub fn new(merkle_root: &Hash256) -> Self {
+        let mut client = Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            pending: Vec::new(),
+            in_flight: Vec::new(),
+            map: SmtMap256::new(),
+        };
+        client.accept(NodeIndex::root(), merkle_root);
+        client
+    }
+
+    /// Sets the maximum number of nodes asked for in one request.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "Batch size must be positive");
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Whether the whole map has been rebuilt.
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Returns the request to send next, or `None` if the sync is complete. The outstanding
+    /// request is returned again until a valid response to it is handled.
+    pub fn next_request(&mut self) -> Option<SyncRequest> {
+        if self.in_flight.is_empty() {
+            let split = self.pending.len().saturating_sub(self.batch_size);
+            self.in_flight = self.pending.split_off(split);
+        }
+        if self.in_flight.is_empty() {
+            return None;
+        }
+        Some(SyncRequest {
+            nodes: self
+                .in_flight
+                .iter()
+                .map(|(node, _)| node.clone())
+                .collect(),
+        })
+    }
+
+    /// Verifies the response to the outstanding request against the already trusted hashes, and
+    /// adds the children to the map if it is valid.
+    pub fn handle_response(&mut self, response: &SyncResponse) -> Result<(), InvalidResponse> {
+        if self.in_flight.is_empty() {
+            return Err(InvalidResponse::Unexpected);
+        }
+        if response.children.len() != self.in_flight.len() {
+            return Err(InvalidResponse::LengthMismatch);
+        }
+        let valid = self
+            .in_flight
+            .iter()
+            .zip(&response.children)
+            .all(|((_, hash), (left, right))| merge_hashes(left, right) == *hash);
+        if !valid {
+            return Err(InvalidResponse::HashMismatch);
+        }
+
+        let in_flight = core::mem::take(&mut self.in_flight);
+        for ((node, _), (left, right)) in in_flight.into_iter().zip(&response.children) {
+            self.accept(node.left_child(), left);
+            self.accept(node.right_child(), right);
+        }
+        Ok(())
+    }
+
+    /// Drives the sync over `transport` until it completes or fails.
+    pub fn run<T: SyncTransport>(&mut self, transport: &mut T) -> Result<(), SyncError<T::Error>> {
+        while let Some(request) = self.next_request() {
+            let response = transport.fetch(&request).map_err(SyncError::Transport)?;
+            self.handle_response(&response)
+                .map_err(SyncError::InvalidResponse)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the rebuilt map, or `None` if the sync is not complete yet.
+    pub fn into_map(self) -> Option<SmtMap256> {
+        if self.is_complete() {
+            Some(self.map)
+        } else {
+            None
+        }
+    }
+
+    // Adds a node with a verified hash to the map, and schedules fetching its children.
+    fn accept(&mut self, index: NodeIndex, hash: &Hash256) {
+        if (*DEFAULT_HASHES)[256 - index.depth] == *hash {
+            return;
+        }
+        self.map.hashes.insert(index.clone(), *hash);
+        if index.depth == 256 {
+            self.map.kvs.insert(index.bit_path, *hash);
+        } else {
+            self.pending.push((index, *hash));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flips a bit of the first child hash of every response.
+    struct CorruptTransport<'a>(InProcessTransport<'a>);
+
+    impl<'a> SyncTransport for CorruptTransport<'a> {
+        type Error = Infallible;
+
+        fn fetch(&mut self, request: &SyncRequest) -> Result<SyncResponse, Self::Error> {
+            let mut response = self.0.fetch(request)?;
+            response.children[0].1[7] ^= 1;
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_sync() {
+        let mut server_map = SmtMap256::new();
+        for i in 0..100_u8 {
+            let mut key = [i.wrapping_mul(37); 32];
+            key[0] = i;
+            server_map.set(&key, [i | 0x80; 32]);
+        }
+        let mut client = SyncClient::new(server_map.merkle_root()).with_batch_size(7);
+        client
+            .run(&mut InProcessTransport::new(&server_map))
+            .unwrap();
+        let map = client.into_map().unwrap();
+        assert_eq!(map.merkle_root(), server_map.merkle_root());
+        assert_eq!(map.kvs, server_map.kvs);
+        assert_eq!(map.hashes, server_map.hashes);
+        assert_eq!(map.diff(&server_map).count(), 0);
+
+        // An empty map needs no request at all.
+        let mut client = SyncClient::new(SmtMap256::new().merkle_root());
+        assert!(client.is_complete());
+        assert_eq!(client.next_request(), None);
+        assert_eq!(client.into_map().unwrap().kvs.len(), 0);
+    }
+
+    #[test]
+    fn test_sync_over_bytes() {
+        let mut server_map = SmtMap256::new();
+        for i in 0..100_u8 {
+            let mut key = [i.wrapping_mul(37); 32];
+            key[0] = i;
+            server_map.set(&key, [i | 0x80; 32]);
+        }
+        let server = SyncServer::new(&server_map);
+        let mut client = SyncClient::new(server_map.merkle_root());
+        while let Some(request) = client.next_request() {
+            let request = SyncRequest::from_bytes(&request.to_bytes()).unwrap();
+            let response = SyncResponse::from_bytes(&server.respond(&request).to_bytes()).unwrap();
+            client.handle_response(&response).unwrap();
+        }
+        assert_eq!(client.into_map().unwrap().kvs, server_map.kvs);
+
+        // A path with bits beyond the depth of the node.
+        let mut bytes = [0; 33];
+        bytes[0] = 8;
+        bytes[1] = 0xff;
+        assert!(SyncRequest::from_bytes(&bytes).is_some());
+        bytes[2] = 1;
+        assert_eq!(SyncRequest::from_bytes(&bytes), None);
+        assert_eq!(SyncRequest::from_bytes(&bytes[..32]), None);
+        assert_eq!(SyncResponse::from_bytes(&[0; 65]), None);
+    }
+
+    #[test]
+    fn test_sync_rejects_corrupted_response() {
+        let mut server_map = SmtMap256::new();
+        for i in 0..100_u8 {
+            let mut key = [i.wrapping_mul(37); 32];
+            key[0] = i;
+            server_map.set(&key, [i | 0x80; 32]);
+        }
+        let mut client = SyncClient::new(server_map.merkle_root());
+        let mut corrupt = CorruptTransport(InProcessTransport::new(&server_map));
+        assert_eq!(
+            client.run(&mut corrupt),
+            Err(SyncError::InvalidResponse(InvalidResponse::HashMismatch))
+        );
+        assert!(!client.is_complete());
+
+        // Truncated response.
+        let request = client.next_request().unwrap();
+        let mut response = SyncServer::new(&server_map).respond(&request);
+        response.children.pop();
+        assert_eq!(
+            client.handle_response(&response),
+            Err(InvalidResponse::LengthMismatch)
+        );
+
+        // The client can carry on with an honest peer.
+        client
+            .run(&mut InProcessTransport::new(&server_map))
+            .unwrap();
+        assert_eq!(client.into_map().unwrap().kvs, server_map.kvs);
+    }
+}
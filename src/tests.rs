@@ -4,8 +4,8 @@ use hex::FromHex;
 use alloc::string::ToString;
 
 #[test]
-fn test_tree_node_index() {
-    let mut index = TreeNodeIndex::leaf(r256("1234567890abcdef1234567890abcdef"));
+fn test_node_index() {
+    let mut index = NodeIndex::leaf(r256("1234567890abcdef1234567890abcdef"));
     assert!(!index.is_left());
     for _ in 0..3 {
         index.move_up();
@@ -13,21 +13,21 @@ fn test_tree_node_index() {
     assert!(index.is_left());
     assert_eq!(
         index,
-        TreeNodeIndex {
+        NodeIndex {
             bit_path: r256("1234567890abcdef1234567890abcd0f"),
             depth: 256 - 3,
         }
     );
     assert_eq!(
         index.sibling().unwrap(),
-        TreeNodeIndex {
+        NodeIndex {
             bit_path: r256("1234567890abcdef1234567890abcd1f"),
             depth: 256 - 3,
         }
     );
 
     // Climb up from the left-most leaf.
-    let mut index = TreeNodeIndex::leaf([0; 32]);
+    let mut index = NodeIndex::leaf([0; 32]);
     for depth in (1..=256).rev() {
         assert_eq!(index.depth, depth);
         assert!(index.is_left());
@@ -44,7 +44,7 @@ fn test_tree_node_index() {
     assert_eq!(index.sibling(), None);
 
     // Climb up from the right-most leaf.
-    let mut index = TreeNodeIndex::leaf(max256());
+    let mut index = NodeIndex::leaf(max256());
     for depth in (1..=256).rev() {
         assert_eq!(index.depth, depth);
         assert!(!index.is_left());
@@ -61,6 +61,57 @@ fn test_tree_node_index() {
     assert_eq!(index.sibling(), None);
 }
 
+#[test]
+fn test_node_index_navigation() {
+    let key = r256("1234567890abcdef1234567890abcdef");
+    let leaf = NodeIndex::leaf(key);
+    assert_eq!(leaf.depth(), 256);
+    assert_eq!(*leaf.bit_path(), key);
+    assert_eq!(NodeIndex::root().parent(), None);
+
+    // The ancestors are the parents up to the root.
+    let ancestors: Vec<NodeIndex> = leaf.ancestors().collect();
+    assert_eq!(ancestors.len(), 256);
+    assert_eq!(ancestors[0], leaf.parent().unwrap());
+    assert_eq!(ancestors[255], NodeIndex::root());
+    for (child, parent) in core::iter::once(&leaf).chain(&ancestors).zip(&ancestors) {
+        assert_eq!(child.parent().as_ref(), Some(parent));
+        assert!(*child == parent.left_child() || *child == parent.right_child());
+        assert!(parent.contains(&key));
+        assert_eq!(parent.depth(), child.depth() - 1);
+    }
+    assert!(leaf.contains(&key));
+    assert!(!leaf.contains(&r256("1234567890abcdef1234567890abcdee")));
+    assert!(!ancestors[3].sibling().unwrap().contains(&key));
+
+    // Keys 0x00..00 and 0x80..00 differ in their 256th bit only.
+    let (left, right) = (NodeIndex::leaf([0; 32]), NodeIndex::leaf(r256("80")));
+    assert_eq!(left.common_ancestor(&right), left.parent().unwrap());
+    assert_eq!(left.common_ancestor(&left), left);
+    assert_eq!(leaf.common_ancestor(&ancestors[10]), ancestors[10]);
+    assert_eq!(ancestors[10].common_ancestor(&leaf), ancestors[10]);
+    // The key starts with 129 bits 0, 0, ..., 0, 1.
+    assert_eq!(leaf.common_ancestor(&left), ancestors[126]);
+    assert_eq!(ancestors[126].depth(), 129);
+    assert_eq!(
+        leaf.common_ancestor(&NodeIndex::leaf(max256())),
+        NodeIndex::root()
+    );
+
+    let mut smt = SmtMap256::new();
+    smt.set(&key, r256("AA"));
+    assert_eq!(*smt.node_hash(&leaf), r256("AA"));
+    assert_eq!(smt.node_hash(&NodeIndex::root()), smt.merkle_root());
+    let (_, proof) = smt.get_with_proof(&[0; 32]);
+    let common = leaf.common_ancestor(&left);
+    assert_eq!(proof.hashes, vec![*smt.node_hash(&common.right_child())]);
+    for height in 0..=256 {
+        assert_eq!(default_hash(height), &DEFAULT_HASHES[height]);
+    }
+    assert_eq!(*smt.node_hash(&left), *default_hash(0));
+    assert_eq!(smt.node_hash(&left.parent().unwrap()), default_hash(1));
+}
+
 #[test]
 fn test_smt_map_256_kv() {
     let mut smt = SmtMap256::new();
@@ -78,6 +129,65 @@ fn test_smt_map_256_kv() {
     assert_eq!(*smt.get(&key), value2);
 }
 
+#[test]
+fn test_smt_map_256_iter() {
+    let mut smt = SmtMap256::new();
+    assert!(smt.is_empty());
+    smt.set(&r256("02"), r256("22"));
+    smt.set(&r256("01"), r256("11"));
+    smt.set(&r256("03"), r256("33"));
+    smt.set(&r256("03"), [0; 32]);
+    assert!(!smt.is_empty());
+    assert_eq!(smt.len(), 2);
+    assert_eq!(
+        smt.iter().collect::<Vec<_>>(),
+        vec![(&r256("01"), &r256("11")), (&r256("02"), &r256("22"))]
+    );
+
+    smt.set(&r256("01"), [0; 32]);
+    smt.set(&r256("02"), [0; 32]);
+    assert!(smt.is_empty());
+    assert_eq!(smt.node_count(), 0);
+}
+
+#[test]
+fn test_smt_map_256_diff() {
+    let mut smt1 = SmtMap256::new();
+    let mut smt2 = SmtMap256::new();
+    assert_eq!(smt1.diff(&smt2).count(), 0);
+
+    for i in 0..32_u8 {
+        smt1.set(&r256(&hex::encode([i])), r256("01"));
+        smt2.set(&r256(&hex::encode([i])), r256("01"));
+    }
+    assert_eq!(smt1.diff(&smt2).count(), 0);
+
+    // A changed value, a key only in `smt1` and a key only in `smt2`.
+    smt1.set(&r256("05"), r256("02"));
+    smt1.set(&max256(), r256("03"));
+    smt2.set(&[0x80; 32], r256("04"));
+
+    // Keys are yielded in tree order.
+    let diff: Vec<_> = smt1.diff(&smt2).collect();
+    assert_eq!(
+        diff,
+        vec![
+            (r256("05"), r256("02"), r256("01")),
+            ([0x80; 32], [0; 32], r256("04")),
+            (max256(), r256("03"), [0; 32]),
+        ]
+    );
+    let reversed: Vec<_> = smt2.diff(&smt1).map(|(k, v1, v2)| (k, v2, v1)).collect();
+    assert_eq!(diff, reversed);
+
+    // Applying the difference makes the maps identical.
+    for (key, _, value) in diff {
+        smt1.set(&key, value);
+    }
+    assert_eq!(smt1.diff(&smt2).count(), 0);
+    assert_eq!(smt1.merkle_root(), smt2.merkle_root());
+}
+
 #[test]
 fn test_smt_map_256_merkle_proof() {
     assert_eq!((*DEFAULT_HASHES)[0], [0; 32]);
@@ -294,9 +404,163 @@ fn test_smt_map_256_merkle_proof_negative_cases() {
     ));
 }
 
+#[test]
+fn test_merkle_proof_bytes() {
+    let mut smt = SmtMap256::new();
+    smt.set(&[0; 32], r256("AA"));
+    smt.set(&max256(), r256("1234"));
+    let (_, proof) = smt.get_with_proof(&r256("C0"));
+    let bytes = proof.to_bytes();
+    assert_eq!(bytes.len(), 32 * 3);
+    assert_eq!(MerkleProof::from_bytes(&bytes), Some(proof));
+
+    let (_, proof) = SmtMap256::new().get_with_proof(&r256("C0"));
+    assert_eq!(proof.to_bytes(), vec![0; 32]);
+    assert_eq!(MerkleProof::from_bytes(&[0; 32]), Some(proof));
+
+    assert_eq!(MerkleProof::from_bytes(&[]), None);
+    assert_eq!(MerkleProof::from_bytes(&bytes[..bytes.len() - 1]), None);
+    assert_eq!(MerkleProof::from_bytes(&bytes[..bytes.len() - 32]), None);
+    assert_eq!(MerkleProof::from_bytes(&[0; 64]), None);
+}
+
+#[test]
+fn test_merkle_proof_ref() {
+    let mut smt = SmtMap256::new();
+    smt.set(&[0; 32], r256("AA"));
+    smt.set(&max256(), r256("1234"));
+    let key = r256("C0");
+    let (value, proof) = smt.get_with_proof(&key);
+    let bytes = proof.to_bytes();
+    let proof_ref = MerkleProofRef::from_bytes(&bytes).unwrap();
+    assert_eq!(proof_ref, MerkleProofRef::from(&proof));
+    assert!(check_merkle_proof_ref(
+        smt.merkle_root(),
+        &key,
+        value,
+        proof_ref
+    ));
+    assert!(!check_merkle_proof_ref(
+        smt.merkle_root(),
+        &r256("C1"),
+        value,
+        proof_ref
+    ));
+
+    // A wrong number of hashes.
+    let proof_ref = MerkleProofRef {
+        hashes: &proof.hashes[..1],
+        ..proof_ref
+    };
+    assert!(!check_merkle_proof_ref(
+        smt.merkle_root(),
+        &key,
+        value,
+        proof_ref
+    ));
+
+    assert_eq!(MerkleProofRef::from_bytes(&[]), None);
+    assert_eq!(MerkleProofRef::from_bytes(&bytes[..bytes.len() - 1]), None);
+    assert_eq!(MerkleProofRef::from_bytes(&bytes[..bytes.len() - 32]), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_smt_map_256_hash_storage() {
+    let mut btree_smt = SmtMap256::new();
+    let mut hash_smt = SmtMap256::<store::HashStorage>::default();
+    assert_eq!(hash_smt.merkle_root(), btree_smt.merkle_root());
+    for i in 0..32_u8 {
+        let (key, value) = (r256(&hex::encode([i * 7])), r256(&hex::encode([i + 1])));
+        assert_eq!(hash_smt.set(&key, value), btree_smt.set(&key, value));
+    }
+    hash_smt.set(&r256("07"), [0; 32]);
+    btree_smt.set(&r256("07"), [0; 32]);
+
+    assert_eq!(hash_smt.merkle_root(), btree_smt.merkle_root());
+    assert_eq!(hash_smt.len(), btree_smt.len());
+    assert_eq!(hash_smt.node_count(), btree_smt.node_count());
+    let key = r256("C0");
+    assert_eq!(
+        hash_smt.get_with_proof(&key),
+        btree_smt.get_with_proof(&key)
+    );
+    assert_eq!(hash_smt.get(&r256("0e")), &r256("03"));
+
+    let mut other = hash_smt.clone();
+    other.set(&key, r256("01"));
+    assert_eq!(
+        hash_smt.diff(&other).collect::<Vec<_>>(),
+        vec![(key, [0; 32], r256("01"))]
+    );
+}
+
+#[test]
+fn test_smt_map_256_msb_first() {
+    use crate::order::{BitOrder, MsbFirst};
+
+    let mut msb_smt = SmtMap256::<BTreeStorage, Keccak256, MsbFirst>::default();
+    let mut lsb_smt = SmtMap256::new();
+    assert_eq!(msb_smt.merkle_root(), lsb_smt.merkle_root());
+
+    // Keys 0 and 1 are adjacent leaves in MSB-first order.
+    msb_smt.set(&r256("00"), r256("AA"));
+    msb_smt.set(&r256("01"), r256("BB"));
+    let mut hash = merge_hashes(&r256("AA"), &r256("BB"));
+    for i in 1..256 {
+        hash = merge_hashes(&hash, &DEFAULT_HASHES[i]);
+    }
+    assert_eq!(*msb_smt.merkle_root(), hash);
+    let (value, proof) = msb_smt.get_with_proof(&r256("01"));
+    assert_eq!(proof.bitmap, l256("80"));
+    assert_eq!(proof.hashes, vec![r256("AA")]);
+    assert!(msb_smt.check_merkle_proof(&r256("01"), value, &proof));
+    assert!(!check_merkle_proof(
+        msb_smt.merkle_root(),
+        &r256("01"),
+        value,
+        &proof
+    ));
+
+    // An MSB-first map is an LSB-first map of the keys with the bits of each byte reversed.
+    for i in 1..32_u8 {
+        let (key, value) = (l256(&hex::encode([i * 7, i])), r256(&hex::encode([i + 1])));
+        msb_smt.set(&key, value);
+        lsb_smt.set(&MsbFirst::reorder(&key), value);
+    }
+    lsb_smt.set(&r256("00"), r256("AA"));
+    lsb_smt.set(&MsbFirst::reorder(&r256("01")), r256("BB"));
+    assert_eq!(msb_smt.merkle_root(), lsb_smt.merkle_root());
+    let key = l256("1c04");
+    let (value, proof) = msb_smt.get_with_proof(&key);
+    let (_, lsb_proof) = lsb_smt.get_with_proof(&MsbFirst::reorder(&key));
+    assert_eq!(proof.bitmap, MsbFirst::reorder(&lsb_proof.bitmap));
+    assert_eq!(proof.hashes, lsb_proof.hashes);
+    assert!(check_merkle_proof_with_order::<Keccak256, MsbFirst>(
+        msb_smt.merkle_root(),
+        &key,
+        value,
+        &proof
+    ));
+    assert!(!check_merkle_proof_with_order::<Keccak256, MsbFirst>(
+        msb_smt.merkle_root(),
+        &l256("1c05"),
+        value,
+        &proof
+    ));
+
+    // The keys are kept in tree order, which `diff` follows.
+    let keys: Vec<Key> = msb_smt.iter().map(|(key, _)| *key).collect();
+    let empty = SmtMap256::<BTreeStorage, Keccak256, MsbFirst>::default();
+    let diff_keys: Vec<Key> = msb_smt.diff(&empty).map(|(key, _, _)| key).collect();
+    assert_eq!(diff_keys, keys);
+    assert_eq!(keys.len(), 33);
+    assert_eq!(keys[..2], [r256("00"), r256("01")]);
+}
+
 // `hex` is the first a few bytes of the desired 32 bytes (the rest bytes are zeros).
 fn l256(hex: &str) -> [u8; 32] {
-    assert!(hex.len() % 2 == 0 && hex.len() <= 64);
+    assert!(hex.len().is_multiple_of(2) && hex.len() <= 64);
     let hex = hex.to_string() + &"0".repeat(64 - hex.len());
     <[u8; 32]>::from_hex(&hex).unwrap()
 }
@@ -304,7 +568,7 @@ fn l256(hex: &str) -> [u8; 32] {
 
 // `hex` is the last a few bytes of the desired 32 bytes (the rest bytes are zeros).
 fn r256(hex: &str) -> [u8; 32] {
-    assert!(hex.len() % 2 == 0 && hex.len() <= 64);
+    assert!(hex.len().is_multiple_of(2) && hex.len() <= 64);
     let hex = "0".repeat(64 - hex.len()) + hex;
     <[u8; 32]>::from_hex(&hex).unwrap()
 }
@@ -0,0 +1,103 @@
+//! Hex text of keys, values, roots and proofs, as read and written by the `smt-map` tool and the
+//! `server` module.
+//!
+//! Hex strings may have a `0x` prefix, and may mix lower and upper case digits. A word (a key, a
+//! value or a hash) may have fewer than 64 digits, in which case it is left-padded with zeros.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::Hash256;
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Returns the lowercase hex digits of `bytes`, without prefix.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|byte| [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]])
+        .map(char::from)
+        .collect()
+}
+
+/// Parses a hex string of an even number of digits into bytes. Returns `None` if `s` is not one.
+// Only the `smt-map` tool reads hex strings other than words.
+#[allow(dead_code)]
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits = digits(s)?;
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| (nibble(pair[0]) << 4) | nibble(pair[1]))
+            .collect(),
+    )
+}
+
+/// Parses a word of 1 to 64 hex digits, left-padded with zeros. Returns `None` if `s` is not one.
+pub fn parse_word(s: &str) -> Option<Hash256> {
+    let digits = digits(s)?;
+    if digits.is_empty() || digits.len() > 64 {
+        return None;
+    }
+    let mut word = [0; 32];
+    for (i, digit) in digits.iter().rev().enumerate() {
+        word[31 - i / 2] |= nibble(*digit) << (4 * (i % 2));
+    }
+    Some(word)
+}
+
+// Returns the digits of `s` without its prefix, or `None` if one is not a hex digit.
+fn digits(s: &str) -> Option<&[u8]> {
+    let digits = s.strip_prefix("0x").unwrap_or(s).as_bytes();
+    if digits.iter().all(u8::is_ascii_hexdigit) {
+        Some(digits)
+    } else {
+        None
+    }
+}
+
+fn nibble(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x01, 0xab]), "01ab");
+        assert_eq!(to_hex(&[]), "");
+        assert_eq!(parse_hex("0x01AB"), Some(vec![0x01, 0xab]));
+        assert_eq!(parse_hex(""), Some(vec![]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("zz"), None);
+        assert_eq!(parse_hex("+1"), None);
+        assert_eq!(parse_hex("0x0x01"), None);
+        assert_eq!(parse_hex("é1"), None);
+
+        let mut word = [0; 32];
+        word[30] = 0x01;
+        word[31] = 0x23;
+        assert_eq!(parse_word("123"), Some(word));
+        assert_eq!(parse_word("0x0123"), Some(word));
+        assert_eq!(parse_word(&to_hex(&word)), Some(word));
+        assert_eq!(
+            parse_word(&("0x".to_string() + &"f".repeat(64))),
+            Some([0xff; 32])
+        );
+        assert_eq!(parse_word(""), None);
+        assert_eq!(parse_word("0x"), None);
+        assert_eq!(parse_word("+1"), None);
+        assert_eq!(parse_word(&"1".repeat(65)), None);
+        assert_eq!(parse_word(&("0x0x".to_string() + &"1".repeat(62))), None);
+    }
+}
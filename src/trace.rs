@@ -0,0 +1,183 @@
+//! Traces of the hash merges along the path of a key, for debugging root mismatches between
+//! implementations and for building ZK witnesses.
+//!
+//! A trace lists the merges from the leaf up to the root, one per level. Tracing does not hook
+//! into the merges of `SmtMap256` itself: the traced operations recompute the merges of the path
+//! from its siblings, which gives exactly the same inputs and outputs.
+
+use alloc::vec::Vec;
+
+use crate::circuit::FullMerkleProof;
+use crate::hasher::Hasher;
+use crate::order::BitOrder;
+use crate::store::Storage;
+use crate::{bit_op, Hash256, Key, MerkleProof, SmtMap256, Value};
+
+/// A merge of the hashes of two sibling nodes into the hash of their parent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MergeStep {
+    /// The depth of the parent. The root has depth of 0, and the leaves have depth of 256.
+    pub depth: usize,
+
+    /// The bit-path from the root to the parent, in the layout of the keys: the first `depth`
+    /// bits of the key (in the bit order of the map), and zeros after.
+    pub path: [u8; 32],
+
+    pub left: Hash256,
+
+    pub right: Hash256,
+
+    pub output: Hash256,
+}
+
+impl<S: Storage, H: Hasher, O: BitOrder> SmtMap256<S, H, O> {
+    /// Same as `set`, but also returns the trace of the merges which compute the new merkle root.
+    pub fn set_traced(&mut self, key: &Key, value: Value) -> (Value, Vec<MergeStep>) {
+        let (_, proof) = self.get_with_proof(key);
+        let old_value = self.set(key, value);
+        // Setting the value of a key does not change the siblings along its path.
+        let trace = trace_with_order::<H, O>(key, &value, &proof).unwrap();
+        (old_value, trace)
+    }
+
+    /// Same as `get_with_proof`, but also returns the trace of the merges which compute the
+    /// merkle root from the value and the proof.
+    pub fn get_with_proof_traced(&self, key: &Key) -> (&Value, MerkleProof, Vec<MergeStep>) {
+        let (value, proof) = self.get_with_proof(key);
+        let trace = trace_with_order::<H, O>(key, value, &proof).unwrap();
+        (value, proof, trace)
+    }
+
+    /// Same as `check_merkle_proof`, but also returns the trace of the merges. The trace is `None`
+    /// if the proof is malformed.
+    pub fn check_merkle_proof_traced(
+        &self,
+        key: &Key,
+        value: &Value,
+        proof: &MerkleProof,
+    ) -> (bool, Option<Vec<MergeStep>>) {
+        let trace = trace_with_order::<H, O>(key, value, proof);
+        let valid = trace
+            .as_ref()
+            .is_some_and(|steps| steps[255].output == *self.merkle_root());
+        (valid, trace)
+    }
+}
+
+/// Returns the trace of the merges which compute the merkle root of an SMT-Map using the hasher
+/// `H` (and the default bit order) from a key-value pair and its proof. The output of the last
+/// step is the root. Returns `None` if the number of hashes does not match the bitmap, or the
+/// hasher rejects some hash.
+pub fn trace_merkle_proof<H: Hasher>(
+    key: &Key,
+    value: &Value,
+    proof: &MerkleProof,
+) -> Option<Vec<MergeStep>> {
+    let levels = FullMerkleProof::from_proof::<H>(proof)?.witness::<H>(key, value)?;
+    let mut path = *key;
+    let trace = levels
+        .iter()
+        .enumerate()
+        .map(|(i, level)| {
+            let depth = 255 - i;
+            bit_op::clear_bit(&mut path, depth);
+            MergeStep {
+                depth,
+                path,
+                left: level.left,
+                right: level.right,
+                output: level.parent,
+            }
+        })
+        .collect();
+    Some(trace)
+}
+
+/// Same as `trace_merkle_proof`, for an SMT-Map using the bit order `O`.
+fn trace_with_order<H: Hasher, O: BitOrder>(
+    key: &Key,
+    value: &Value,
+    proof: &MerkleProof,
+) -> Option<Vec<MergeStep>> {
+    let proof = MerkleProof {
+        bitmap: O::reorder(&proof.bitmap),
+        hashes: proof.hashes.clone(),
+    };
+    let mut trace = trace_merkle_proof::<H>(&O::reorder(key), value, &proof)?;
+    for step in trace.iter_mut() {
+        step.path = O::reorder(&step.path);
+    }
+    Some(trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Keccak256;
+    use crate::merge_hashes;
+
+    #[test]
+    fn test_trace() {
+        let mut smt = SmtMap256::new();
+        smt.set(&[0; 32], [0xaa; 32]);
+        let key = [0x81; 32];
+        let (old_value, trace) = smt.set_traced(&key, [0xbb; 32]);
+        assert_eq!(old_value, [0; 32]);
+        assert_eq!(trace.len(), 256);
+
+        let mut hash = [0xbb; 32];
+        for (i, step) in trace.iter().enumerate() {
+            assert_eq!(step.depth, 255 - i);
+            assert!(step.left == hash || step.right == hash);
+            assert_eq!(step.output, merge_hashes(&step.left, &step.right));
+            hash = step.output;
+        }
+        assert_eq!(hash, *smt.merkle_root());
+
+        // The paths are the prefixes of the key.
+        assert_eq!(trace[0].path, {
+            let mut path = key;
+            path[31] = 0x01;
+            path
+        });
+        assert_eq!(trace[247].path, {
+            let mut path = [0; 32];
+            path[0] = 0x81;
+            path
+        });
+        assert_eq!(trace[249].path, {
+            let mut path = [0; 32];
+            path[0] = 0x01;
+            path
+        });
+        assert_eq!(trace[255].path, [0; 32]);
+
+        let (value, proof, get_trace) = smt.get_with_proof_traced(&key);
+        assert_eq!(*value, [0xbb; 32]);
+        assert_eq!(get_trace, trace);
+
+        let (valid, check_trace) = smt.check_merkle_proof_traced(&key, value, &proof);
+        assert!(valid);
+        assert_eq!(check_trace, Some(trace.clone()));
+
+        // A wrong value diverges from the trace from the leaf up.
+        let (valid, check_trace) = smt.check_merkle_proof_traced(&key, &[1; 32], &proof);
+        assert!(!valid);
+        let check_trace = check_trace.unwrap();
+        assert!(check_trace
+            .iter()
+            .zip(trace.iter())
+            .all(|(wrong, right)| wrong.output != right.output));
+
+        let mut malformed = proof;
+        malformed.hashes.push([0; 32]);
+        assert_eq!(
+            smt.check_merkle_proof_traced(&key, value, &malformed),
+            (false, None)
+        );
+        assert_eq!(
+            trace_merkle_proof::<Keccak256>(&key, value, &malformed),
+            None
+        );
+    }
+}
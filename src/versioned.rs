@@ -0,0 +1,212 @@
+//! A map with committed versions sharing their nodes in a `Forest`, and pruning of old versions.
+//!
+//! Updates go to the head of the map, and `commit` freezes the head as a new version. Versions
+//! share all their unchanged nodes, so a version only costs the paths of the keys set since the
+//! previous one. `prune_before` drops old versions, which deletes the nodes no retained version
+//! (or the head) can reach.
+
+use alloc::collections::btree_map::BTreeMap;
+use core::fmt;
+
+use crate::forest::{Forest, MapId};
+use crate::{Hash256, Key, MerkleProof, Value};
+
+/// Version numbers, counting the commits from 0.
+pub type Version = u64;
+
+/// A version of a `VersionedSmtMap` has missing or corrupt nodes. The head is reported as the
+/// version it would have once committed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CorruptVersion(pub Version);
+
+impl fmt::Display for CorruptVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "missing or corrupt nodes in version {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CorruptVersion {}
+
+/// An `SmtMap256`-compatible map keeping its committed versions.
+pub struct VersionedSmtMap {
+    forest: Forest,
+
+    // The uncommitted state.
+    head: MapId,
+
+    // The retained versions.
+    versions: BTreeMap<Version, MapId>,
+
+    next_version: Version,
+}
+
+impl Default for VersionedSmtMap {
+    fn default() -> Self {
+        let mut forest = Forest::new();
+        let head = forest.create_map();
+        Self {
+            forest,
+            head,
+            versions: BTreeMap::new(),
+            next_version: 0,
+        }
+    }
+}
+
+impl VersionedSmtMap {
+    /// Returns a new map where all keys have the default value (zero), without versions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of a key in the head. Returns the old value of the key.
+    pub fn set(&mut self, key: &Key, value: Value) -> Value {
+        self.forest.set(self.head, key, value)
+    }
+
+    /// Returns the value of a key in the head.
+    pub fn get(&self, key: &Key) -> Value {
+        self.forest.get(self.head, key)
+    }
+
+    /// Returns the value of a key in the head with merkle proof.
+    pub fn get_with_proof(&self, key: &Key) -> (Value, MerkleProof) {
+        self.forest.get_with_proof(self.head, key)
+    }
+
+    /// Returns the merkle root of the head.
+    pub fn merkle_root(&self) -> &Hash256 {
+        self.forest.merkle_root(self.head)
+    }
+
+    /// Freezes the head as a new version, and returns the version.
+    pub fn commit(&mut self) -> Version {
+        let version = self.next_version;
+        let id = self.forest.fork(self.head);
+        self.versions.insert(version, id);
+        self.next_version += 1;
+        version
+    }
+
+    /// Returns the merkle root of a version, or `None` if the version is not retained.
+    pub fn root_at(&self, version: Version) -> Option<&Hash256> {
+        let id = self.versions.get(&version)?;
+        Some(self.forest.merkle_root(*id))
+    }
+
+    /// Returns the value of a key in a version, or `None` if the version is not retained.
+    pub fn get_at(&self, version: Version, key: &Key) -> Option<Value> {
+        let id = self.versions.get(&version)?;
+        Some(self.forest.get(*id, key))
+    }
+
+    /// Returns the value of a key in a version with merkle proof, or `None` if the version is not
+    /// retained.
+    pub fn get_with_proof_at(&self, version: Version, key: &Key) -> Option<(Value, MerkleProof)> {
+        let id = self.versions.get(&version)?;
+        Some(self.forest.get_with_proof(*id, key))
+    }
+
+    /// Returns an iterator over the retained versions, from the oldest.
+    pub fn versions(&self) -> impl Iterator<Item = Version> + '_ {
+        self.versions.keys().copied()
+    }
+
+    /// Drops the versions before `version`, and deletes the nodes which are no longer reachable
+    /// from the retained versions and the head. Returns the number of deleted nodes.
+    pub fn prune_before(&mut self, version: Version) -> usize {
+        let node_count = self.forest.node_count();
+        let retained = self.versions.split_off(&version);
+        for id in self.versions.values() {
+            self.forest.drop_map(*id);
+        }
+        self.versions = retained;
+        node_count - self.forest.node_count()
+    }
+
+    /// Returns the number of stored nodes of all the retained versions and the head.
+    pub fn node_count(&self) -> usize {
+        self.forest.node_count()
+    }
+
+    /// Checks that the retained versions and the head have all their nodes, and valid proofs for
+    /// all their keys with non-default values (see `Forest::check_map`). Returns the first version
+    /// failing the check.
+    pub fn integrity_check(&self) -> Result<(), CorruptVersion> {
+        for (version, id) in &self.versions {
+            if !self.forest.check_map(*id) {
+                return Err(CorruptVersion(*version));
+            }
+        }
+        if !self.forest.check_map(self.head) {
+            return Err(CorruptVersion(self.next_version));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check_merkle_proof, SmtMap256};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_versioned_smt_map() {
+        let mut versioned = VersionedSmtMap::new();
+        let mut smt = SmtMap256::new();
+        let mut roots = Vec::new();
+        for i in 0..8_u8 {
+            for j in 0..4_u8 {
+                let key = [i.wrapping_mul(31).wrapping_add(j); 32];
+                versioned.set(&key, [i + 1; 32]);
+                smt.set(&key, [i + 1; 32]);
+            }
+            assert_eq!(versioned.commit(), i as Version);
+            assert_eq!(versioned.merkle_root(), smt.merkle_root());
+            roots.push(*smt.merkle_root());
+        }
+        versioned.set(&[0; 32], [0xaa; 32]);
+        assert_eq!(versioned.get(&[0; 32]), [0xaa; 32]);
+        assert_eq!(versioned.get_at(7, &[0; 32]), Some([1; 32]));
+        assert_eq!(versioned.get_at(8, &[0; 32]), None);
+        assert_eq!(versioned.integrity_check(), Ok(()));
+
+        // Pruning deletes the nodes only the old versions reach.
+        let node_count = versioned.node_count();
+        assert_eq!(versioned.prune_before(0), 0);
+        let deleted = versioned.prune_before(5);
+        assert!(deleted > 0);
+        assert_eq!(versioned.node_count(), node_count - deleted);
+        assert_eq!(versioned.versions().collect::<Vec<_>>(), [5, 6, 7]);
+        assert_eq!(versioned.root_at(4), None);
+        assert_eq!(versioned.get_with_proof_at(4, &[0; 32]), None);
+        assert_eq!(versioned.integrity_check(), Ok(()));
+
+        // The retained versions still prove their values.
+        for version in 5..8 {
+            let root = versioned.root_at(version).unwrap();
+            assert_eq!(*root, roots[version as usize]);
+            for i in 0..8_u8 {
+                let key = [i.wrapping_mul(31); 32];
+                let (value, proof) = versioned.get_with_proof_at(version, &key).unwrap();
+                assert_eq!(value[0] != 0, i as Version <= version);
+                assert!(check_merkle_proof(root, &key, &value, &proof));
+            }
+        }
+
+        // Pruning all versions keeps the head only.
+        versioned.prune_before(8);
+        assert_eq!(versioned.versions().count(), 0);
+        assert_eq!(versioned.get(&[0; 32]), [0xaa; 32]);
+        assert_eq!(versioned.integrity_check(), Ok(()));
+        let (value, proof) = versioned.get_with_proof(&[31; 32]);
+        assert!(check_merkle_proof(
+            versioned.merkle_root(),
+            &[31; 32],
+            &value,
+            &proof
+        ));
+    }
+}
@@ -0,0 +1,52 @@
+//! Builds the crate as a static library, and compiles and runs the C test program in
+//! `tests/ffi/test.c` against it and `include/smt_map.h`.
+
+#![cfg(feature = "ffi")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap();
+    assert!(status.success(), "{:?} failed with {}", command, status);
+}
+
+#[test]
+fn test_c_program() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+
+    // A separate target directory avoids waiting on the lock of the one running this test.
+    let target_dir = out_dir.join("target");
+    run(Command::new(env!("CARGO"))
+        .current_dir(manifest_dir)
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "ffi",
+            "--crate-type",
+            "staticlib",
+        ])
+        .arg("--target-dir")
+        .arg(&target_dir));
+
+    let program = out_dir.join("test");
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let mut command = Command::new(cc);
+    command
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests/ffi/test.c"))
+        .arg(target_dir.join("debug/libsmt_map.a"))
+        .arg("-o")
+        .arg(&program);
+    if cfg!(target_os = "linux") {
+        command.args(["-lpthread", "-ldl", "-lm"]);
+    }
+    run(&mut command);
+    run(&mut Command::new(&program));
+}
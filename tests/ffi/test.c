@@ -0,0 +1,97 @@
+/* Exercises the C ABI with the vectors of `test_smt_map_256_merkle_proof` in src/tests.rs. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "smt_map.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                      \
+    do {                                                                 \
+        if (!(cond)) {                                                   \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,       \
+                    __LINE__, #cond);                                    \
+            failures++;                                                  \
+        }                                                                \
+    } while (0)
+
+static void from_hex(const char *hex, uint8_t out[32]) {
+    for (int i = 0; i < 32; i++) {
+        unsigned int byte;
+        sscanf(hex + 2 * i, "%2x", &byte);
+        out[i] = (uint8_t)byte;
+    }
+}
+
+int main(void) {
+    uint8_t zero[32] = {0};
+    uint8_t max_key[32], key[32] = {0}, value[32], out[32], root[32], expected[32];
+    uint8_t proof[SMT_MAX_PROOF_LEN];
+    size_t proof_len = 0;
+    memset(max_key, 0xff, 32);
+    key[31] = 0xc0;
+
+    SmtMap256 *map = smt_map_new();
+    CHECK(map != NULL);
+
+    /* An empty map. */
+    CHECK(smt_map_root(map, root) == SMT_OK);
+    from_hex("a7ff9e28ffd3def443d324547688c2c4eb98edf7da757d6bfa22bff55b9ce24a", expected);
+    CHECK(memcmp(root, expected, 32) == 0);
+
+    /* Key 0x00..00 = 0xAA, and the max key = 0x1234. */
+    memset(value, 0, 32);
+    value[31] = 0xaa;
+    CHECK(smt_map_set(map, zero, value, out) == SMT_OK);
+    CHECK(memcmp(out, zero, 32) == 0);
+    memset(value, 0, 32);
+    value[30] = 0x12;
+    value[31] = 0x34;
+    CHECK(smt_map_set(map, max_key, value, NULL) == SMT_OK);
+    CHECK(smt_map_get(map, max_key, out) == SMT_OK);
+    CHECK(memcmp(out, value, 32) == 0);
+    CHECK(smt_map_root(map, root) == SMT_OK);
+    from_hex("514f973cd76a4e5430119524ae291a3227f1e81f69f5bf2c61a36d2a6c3e239e", expected);
+    CHECK(memcmp(root, expected, 32) == 0);
+
+    /* Proof of an unset key. */
+    CHECK(smt_map_get_with_proof(map, key, out, proof, 10, &proof_len) ==
+          SMT_ERR_BUFFER_TOO_SMALL);
+    CHECK(proof_len == 32 * 3);
+    CHECK(smt_map_get_with_proof(map, key, out, proof, sizeof(proof), &proof_len) == SMT_OK);
+    CHECK(memcmp(out, zero, 32) == 0);
+    CHECK(proof_len == 32 * 3);
+    CHECK(smt_verify_merkle_proof(root, key, zero, proof, proof_len) == 1);
+    CHECK(smt_verify_merkle_proof(root, key, value, proof, proof_len) == 0);
+    CHECK(smt_verify_merkle_proof(root, key, zero, proof, proof_len - 1) ==
+          SMT_ERR_MALFORMED_PROOF);
+
+    /* Decode and re-encode the proof. */
+    uint8_t bitmap[32], hashes[32 * 2], encoded[SMT_MAX_PROOF_LEN];
+    size_t num_hashes = 0, encoded_len = 0;
+    CHECK(smt_merkle_proof_decode(proof, proof_len, bitmap, hashes, 1, &num_hashes) ==
+          SMT_ERR_BUFFER_TOO_SMALL);
+    CHECK(num_hashes == 2);
+    CHECK(smt_merkle_proof_decode(proof, proof_len, bitmap, hashes, 2, &num_hashes) == SMT_OK);
+    from_hex("0200000000000000000000000000000000000000000000000000000000000080", expected);
+    CHECK(memcmp(bitmap, expected, 32) == 0);
+    from_hex("d6f751104ddfead9549c96fabdbd4d2fc6876c8cd9a49ea4a821de938f71a011", expected);
+    CHECK(memcmp(hashes, expected, 32) == 0);
+    CHECK(smt_merkle_proof_encode(bitmap, hashes, 2, encoded, sizeof(encoded), &encoded_len) ==
+          SMT_OK);
+    CHECK(encoded_len == proof_len && memcmp(encoded, proof, proof_len) == 0);
+    CHECK(smt_merkle_proof_encode(bitmap, hashes, 1, encoded, sizeof(encoded), &encoded_len) ==
+          SMT_ERR_MALFORMED_PROOF);
+
+    CHECK(smt_map_get(NULL, key, out) == SMT_ERR_NULL_POINTER);
+    smt_map_free(map);
+    smt_map_free(NULL);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("all checks passed\n");
+    return 0;
+}
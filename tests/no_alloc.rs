@@ -0,0 +1,59 @@
+//! Checks that `check_merkle_proof_ref` verifies encoded proofs without touching the heap. Runs
+//! with any set of features, including none.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use smt_map::{check_merkle_proof_ref, MerkleProofRef};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn word(hex: &str) -> [u8; 32] {
+    let mut word = [0; 32];
+    for (i, byte) in word.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    word
+}
+
+#[test]
+fn test_verify_without_allocation() {
+    // The proof of key 0xC0 after setting key 0x00 to 0xAA and key 0xFF..FF to 0x1234 (see
+    // `test_smt_map_256_merkle_proof`).
+    let root = word("514f973cd76a4e5430119524ae291a3227f1e81f69f5bf2c61a36d2a6c3e239e");
+    let mut key = [0; 32];
+    key[31] = 0xc0;
+    let mut proof = Vec::new();
+    proof.extend_from_slice(&word(
+        "0200000000000000000000000000000000000000000000000000000000000080",
+    ));
+    proof.extend_from_slice(&word(
+        "d6f751104ddfead9549c96fabdbd4d2fc6876c8cd9a49ea4a821de938f71a011",
+    ));
+    proof.extend_from_slice(&word(
+        "5a7ef746ad33334b4fbd7406a1a4ffa5c5f959199448d5ae6ed39b4a9d6ebe5a",
+    ));
+
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst);
+    let proof_ref = MerkleProofRef::from_bytes(&proof).unwrap();
+    assert!(check_merkle_proof_ref(&root, &key, &[0; 32], proof_ref));
+    assert!(!check_merkle_proof_ref(&root, &key, &[1; 32], proof_ref));
+    assert!(MerkleProofRef::from_bytes(&proof[..64]).is_none());
+    assert_eq!(ALLOCATIONS.load(Ordering::SeqCst), allocations);
+}
@@ -0,0 +1,149 @@
+//! Starts `smt-map-server` on localhost and checks its responses.
+
+#![cfg(feature = "server")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+use serde_json::Value as Json;
+use smt_map::{check_merkle_proof, MerkleProof};
+
+const TOKEN: &str = "test-token";
+
+// Kills the server when the test ends.
+struct Server {
+    child: Child,
+    address: String,
+}
+
+impl Server {
+    fn start(dir: &std::path::Path) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_smt-map-server"))
+            .arg(dir)
+            .args(["--listen", "127.0.0.1:0"])
+            .env("SMT_MAP_SERVER_TOKEN", TOKEN)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let address = line
+            .trim()
+            .strip_prefix("listening on ")
+            .unwrap()
+            .to_string();
+        Self { child, address }
+    }
+
+    // Sends a request and returns the status and the JSON body of the response.
+    fn request(&self, method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, Json) {
+        let mut stream = TcpStream::connect(&self.address).unwrap();
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            body.len()
+        );
+        if let Some(token) = token {
+            request += &format!("Authorization: Bearer {}\r\n", token);
+        }
+        request += "\r\n";
+        request += body;
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn get(&self, path: &str) -> (u16, Json) {
+        self.request("GET", path, None, "")
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn word(json: &Json) -> [u8; 32] {
+    let mut word = [0; 32];
+    word.copy_from_slice(&hex::decode(json.as_str().unwrap()).unwrap());
+    word
+}
+
+fn check_proof(server: &Server, key: &str, expected_value: &str) {
+    let (status, body) = server.get(&format!("/proof/{}", key));
+    assert_eq!(status, 200);
+    assert_eq!(body["value"], expected_value);
+    let proof = MerkleProof::from_bytes(&hex::decode(body["proof"].as_str().unwrap()).unwrap());
+    let root = word(&server.get("/root").1["root"]);
+    assert_eq!(word(&body["root"]), root);
+    assert!(check_merkle_proof(
+        &root,
+        &word(&body["key"]),
+        &word(&body["value"]),
+        &proof.unwrap()
+    ));
+}
+
+#[test]
+fn test_server() {
+    let dir = std::env::temp_dir().join(format!("smt_map_server_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server = Server::start(&dir);
+    let zero = "0".repeat(64);
+    let (key1, value1) = (format!("{:0>64}", "01"), format!("{:0>64}", "aa"));
+    let (key2, value2) = (format!("{:0>64}", "02"), format!("{:0>64}", "bb"));
+
+    let (status, empty_root) = server.get("/root");
+    assert_eq!(status, 200);
+    check_proof(&server, &key1, &zero);
+
+    // Updates need the token.
+    let batch = format!(
+        r#"{{"updates": [{{"key": "{}", "value": "{}"}}, {{"key": "0x2", "value": "bb"}}]}}"#,
+        key1, value1
+    );
+    assert_eq!(server.request("POST", "/batch", None, &batch).0, 401);
+    assert_eq!(
+        server.request("POST", "/batch", Some("wrong"), &batch).0,
+        401
+    );
+    assert_eq!(server.get("/root").1, empty_root);
+
+    let (status, body) = server.request("POST", "/batch", Some(TOKEN), &batch);
+    assert_eq!(status, 200);
+    assert_eq!(server.get("/root").1, body);
+    assert_ne!(body, empty_root);
+
+    let (status, body) = server.get(&format!("/value/{}", key2));
+    assert_eq!(status, 200);
+    assert_eq!(body["value"], value2);
+    check_proof(&server, &key1, &value1);
+    check_proof(&server, &key2, &value2);
+    check_proof(&server, "03", &zero);
+
+    // Malformed requests.
+    assert_eq!(server.get("/value/xyz").0, 400);
+    assert_eq!(server.get("/nothing").0, 404);
+    assert_eq!(server.request("DELETE", "/root", None, "").0, 405);
+    let (status, body) = server.request("POST", "/batch", Some(TOKEN), r#"{"updates": 1}"#);
+    assert_eq!(status, 400);
+    assert!(body["error"].is_string());
+
+    // The updates survive a restart.
+    let root = server.get("/root").1;
+    drop(server);
+    let server = Server::start(&dir);
+    assert_eq!(server.get("/root").1, root);
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}